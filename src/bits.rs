@@ -0,0 +1,199 @@
+use crate::code::{Code, CodeWord, Depth};
+use crate::node::{Node, NodeType, RealNum};
+
+use itertools::Itertools;
+use std::collections::HashMap;
+use xxhash_rust::xxh3::Xxh3Builder;
+
+//The actual code word each symbol is sent as, with false for 0 and true for 1
+pub type Bits = Vec<bool>;
+pub type Codebook<T> = HashMap<CodeWord<T>, Bits, Xxh3Builder>;
+
+pub fn format_bits(bits: &[bool]) -> String {
+    bits.iter()
+        .map(|&bit| if bit { '1' } else { '0' })
+        .collect()
+}
+
+//"symbol:bits" for every code word, in order of symbol
+pub fn format_codebook<T>(codebook: &Codebook<T>) -> String {
+    codebook
+        .iter()
+        .sorted_by_key(|(code_word, _)| code_word.source_symbol())
+        .map(|(code_word, bits)| {
+            format!("{}:{}", code_word.source_symbol(), format_bits(bits))
+        })
+        .join(" ")
+}
+
+//Reads the code words off a tree, 0 for the first child and 1 for the second
+pub fn codebook_from_node<T>(node: &Node<T>) -> Codebook<T>
+where
+    T: RealNum,
+{
+    fn helper<T: RealNum>(
+        node: &Node<T>,
+        path: &mut Bits,
+        codebook: &mut Codebook<T>,
+    ) {
+        match node.node_type() {
+            NodeType::Leaf(symbol) => {
+                codebook.insert(
+                    CodeWord::new(symbol.clone(), node.probability()),
+                    path.clone(),
+                );
+            }
+            NodeType::Branch(children) => {
+                for (bit, child) in
+                    [false, true].into_iter().zip(children.iter())
+                {
+                    path.push(bit);
+                    helper(child, path, codebook);
+                    path.pop();
+                }
+            }
+        }
+    }
+    let mut codebook = Codebook::default();
+    helper(node, &mut vec![], &mut codebook);
+    codebook
+}
+
+//Canonical Huffman code words for a length assignment: code words are
+//handed out in order of length, then symbol, each being the previous one
+//plus one, padded with zeros to its length. None if the lengths break
+//Kraft's inequality, which is exactly when the count runs out of bits.
+pub fn canonical_codebook<T>(code: &Code<T>) -> Option<Codebook<T>>
+where
+    T: RealNum,
+{
+    let mut codebook = Codebook::default();
+    let mut bits: Option<Bits> = None;
+    for (code_word, &depth) in
+        code.iter().sorted_by(|(a, a_depth), (b, b_depth)| {
+            a_depth
+                .cmp(b_depth)
+                .then_with(|| a.source_symbol().cmp(b.source_symbol()))
+        })
+    {
+        let mut next = match bits {
+            None => vec![],
+            Some(mut bits) => {
+                increment(&mut bits)?;
+                bits
+            }
+        };
+        next.resize(depth as usize, false);
+        codebook.insert(code_word.clone(), next.clone());
+        bits = Some(next);
+    }
+    Some(codebook)
+}
+
+//Adds one to a big endian binary number, None if it overflows
+fn increment(bits: &mut Bits) -> Option<()> {
+    let last_zero = bits.iter().rposition(|&bit| !bit)?;
+    bits[last_zero] = true;
+    bits[last_zero + 1..].fill(false);
+    Some(())
+}
+
+//Whether no code word is a prefix of another. In sorted order a code word
+//is followed directly by the ones it's a prefix of.
+#[allow(dead_code)]
+pub fn is_prefix_free<T>(codebook: &Codebook<T>) -> bool {
+    codebook
+        .values()
+        .sorted()
+        .tuple_windows()
+        .all(|(a, b)| !b.starts_with(a))
+}
+
+#[allow(dead_code)]
+pub fn code_from_codebook<T>(codebook: &Codebook<T>) -> Code<T>
+where
+    T: RealNum,
+{
+    codebook
+        .iter()
+        .map(|(code_word, bits)| (code_word.clone(), bits.len() as Depth))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::FromNode;
+    use crate::reduction::distinct_huffman_codes;
+    use crate::source::Source;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn codebook_from_node_test() {
+        let tree = Node::new_branch(
+            Node::new_leaf(4, 'a'),
+            Node::new_branch(
+                Node::new_leaf(3, 'b'),
+                Node::new_branch(
+                    Node::new_leaf(2, 'c'),
+                    Node::new_leaf(1, 'd'),
+                ),
+            ),
+        );
+        let codebook = codebook_from_node(&tree);
+        assert_eq!(format_codebook(&codebook), "a:0 b:10 c:110 d:111");
+        assert!(is_prefix_free(&codebook));
+        assert_eq!(code_from_codebook(&codebook), Code::from_node(&tree));
+        assert_eq!(
+            format_codebook(&codebook_from_node(&Node::new_leaf(1, 'a'))),
+            "a:"
+        );
+    }
+
+    #[test]
+    fn canonical_codebook_test() {
+        let code: Code<u32> = [('a', 2), ('b', 1), ('c', 3), ('d', 3)]
+            .into_iter()
+            .map(|(symbol, depth)| (CodeWord::new(symbol, 1), depth))
+            .collect();
+        let codebook = canonical_codebook(&code).unwrap();
+        assert_eq!(format_codebook(&codebook), "a:10 b:0 c:110 d:111");
+
+        //Kraft's inequality with slack leaves the last code words unused
+        let mut short = code.clone();
+        short.remove(&CodeWord::new('d', 1));
+        let codebook = canonical_codebook(&short).unwrap();
+        assert_eq!(format_codebook(&codebook), "a:10 b:0 c:110");
+
+        let mut long = code.clone();
+        long.insert(CodeWord::new('e', 1), 3);
+        assert!(canonical_codebook(&long).is_none());
+
+        let mut rng = StdRng::seed_from_u64(21);
+        for size in 2..=9 {
+            let source = Source::new(size, &mut rng);
+            for tree in distinct_huffman_codes(source.to_leaves_vec()) {
+                let code = Code::from_node(&tree);
+                let codebook = canonical_codebook(&code).unwrap();
+                assert!(is_prefix_free(&codebook));
+                assert_eq!(code_from_codebook(&codebook), code);
+            }
+        }
+    }
+
+    #[test]
+    fn is_prefix_free_test() {
+        let codebook: Codebook<u32> = [vec![false], vec![false, true]]
+            .into_iter()
+            .zip(['a', 'b'])
+            .map(|(bits, symbol)| (CodeWord::new(symbol, 1), bits))
+            .collect();
+        assert!(!is_prefix_free(&codebook));
+        let mut codebook = codebook;
+        codebook.insert(CodeWord::new('b', 1), vec![true]);
+        assert!(is_prefix_free(&codebook));
+        codebook.insert(CodeWord::new('c', 1), vec![true]);
+        assert!(!is_prefix_free(&codebook));
+    }
+}
@@ -1,64 +1,186 @@
+use crate::dense::{DenseCode, DenseSource};
 use crate::node::{Node, NodeType, RealNum};
-use crate::source::Source;
+use crate::source::{Source, Symbol};
 
 use itertools::Itertools;
 use rayon::prelude::*;
-use sorted_vec::SortedVec;
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::hash::Hash;
 use std::iter::zip;
 use xxhash_rust::xxh3::Xxh3Builder;
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CodeWord<T> {
-    source_symbol: char,
+    source_symbol: Symbol,
     probability: T,
 }
 
 pub type Probability = u32;
-pub type Depth = u8;
+pub type Depth = u16;
 pub type Code<T> = HashMap<CodeWord<T>, Depth, Xxh3Builder>;
 
 impl<T> CodeWord<T> {
-    pub fn new(source_symbol: char, probability: T) -> CodeWord<T> {
+    pub fn new<S: Into<Symbol>>(
+        source_symbol: S,
+        probability: T,
+    ) -> CodeWord<T> {
         CodeWord {
-            source_symbol,
+            source_symbol: source_symbol.into(),
             probability,
         }
     }
+    pub fn source_symbol(&self) -> &Symbol {
+        &self.source_symbol
+    }
+    pub fn probability(&self) -> &T {
+        &self.probability
+    }
+}
+
+//A Code is keyed by CodeWord, which JSON can't use as a map key, so codes are
+//written as a list of (code word, depth) entries sorted by symbol.
+//Use with #[serde(with = "crate::code::code_entries")]
+pub mod code_entries {
+    use super::{Code, CodeWord, Depth, New};
+    use itertools::Itertools;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::hash::Hash;
+
+    pub fn serialize<T, S>(
+        code: &Code<T>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        T: Serialize,
+        S: Serializer,
+    {
+        code.iter()
+            .sorted_by_key(|(code_word, _)| &code_word.source_symbol)
+            .collect_vec()
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Code<T>, D::Error>
+    where
+        T: Deserialize<'de> + Hash + Eq,
+        D: Deserializer<'de>,
+    {
+        let entries = Vec::<(CodeWord<T>, Depth)>::deserialize(deserializer)?;
+        let mut code = Code::new();
+        code.extend(entries);
+        Ok(code)
+    }
+}
+
+//The total probability of the symbols a code gives shorter code words than
+//another code, and of those it gives longer ones. Kept as two sums so that
+//unsigned and arbitrary precision probabilities compare exactly; advantages
+//are ordered by `gained - lost`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Advantage<T> {
+    pub gained: T,
+    pub lost: T,
+}
+
+impl<T> Advantage<T>
+where
+    T: RealNum,
+{
+    pub fn zero() -> Advantage<T> {
+        Advantage {
+            gained: T::zero(),
+            lost: T::zero(),
+        }
+    }
+    pub fn signum(&self) -> Ordering {
+        self.gained.cmp(&self.lost)
+    }
+}
+
+impl<T> PartialEq for Advantage<T>
+where
+    T: RealNum,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Advantage<T> where T: RealNum {}
+
+impl<T> PartialOrd for Advantage<T>
+where
+    T: RealNum,
+{
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Advantage<T>
+where
+    T: RealNum,
+{
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.gained.clone() + other.lost.clone())
+            .cmp(&(other.gained.clone() + self.lost.clone()))
+    }
 }
 
 pub trait CompetitiveOrd {
-    fn competitive_advantage(&self, other: &Self) -> Option<i64>;
+    type Weight;
+    //None if the codes don't cover the same code words
+    fn competitive_advantage(
+        &self,
+        other: &Self,
+    ) -> Option<Advantage<Self::Weight>>;
     fn beats(&self, other: &Self) -> Option<bool>;
+    #[allow(dead_code)]
     fn loses(&self, other: &Self) -> Option<bool>;
+    #[allow(dead_code)]
     fn ties(&self, other: &Self) -> Option<bool>;
 }
 
-impl CompetitiveOrd for Code<Probability> {
-    fn competitive_advantage(&self, other: &Code<Probability>) -> Option<i64> {
-        let competitive_advantage: i64 = self
-            .iter()
-            .map(|(code_word, depth)| match depth.cmp(&other[code_word]) {
-                Ordering::Less => code_word.probability as i64,
-                Ordering::Equal => 0,
-                Ordering::Greater => -(code_word.probability as i64),
-            })
-            .sum();
-        Some(competitive_advantage)
+impl<T> CompetitiveOrd for Code<T>
+where
+    T: RealNum,
+{
+    type Weight = T;
+
+    fn competitive_advantage(&self, other: &Code<T>) -> Option<Advantage<T>> {
+        if self.len() != other.len() {
+            return None;
+        }
+        let mut advantage = Advantage::zero();
+        for (code_word, depth) in self.iter() {
+            match depth.cmp(other.get(code_word)?) {
+                Ordering::Less => {
+                    advantage.gained =
+                        advantage.gained + code_word.probability.clone()
+                }
+                Ordering::Equal => (),
+                Ordering::Greater => {
+                    advantage.lost =
+                        advantage.lost + code_word.probability.clone()
+                }
+            }
+        }
+        Some(advantage)
     }
     fn beats(&self, other: &Self) -> Option<bool> {
-        Some(self.competitive_advantage(other)? > 0)
+        Some(self.competitive_advantage(other)?.signum() == Ordering::Greater)
     }
     fn loses(&self, other: &Self) -> Option<bool> {
-        Some(self.competitive_advantage(other)? < 0)
+        Some(self.competitive_advantage(other)?.signum() == Ordering::Less)
     }
     fn ties(&self, other: &Self) -> Option<bool> {
-        Some(self.competitive_advantage(other)? == 0)
+        Some(self.competitive_advantage(other)?.signum() == Ordering::Equal)
     }
 }
 
+#[allow(dead_code)]
 pub trait New {
     fn new() -> Self;
 }
@@ -69,21 +191,27 @@ impl<T> New for Code<T> {
     }
 }
 
-pub trait FromNode {
-    fn from_node(node: &Node<Probability>) -> Self;
+pub trait FromNode<T>
+where
+    T: RealNum,
+{
+    fn from_node(node: &Node<T>) -> Self;
 }
 
-impl FromNode for Code<Probability> {
-    fn from_node(node: &Node<Probability>) -> Code<Probability> {
-        fn helper(
-            node: &Node<Probability>,
+impl<T> FromNode<T> for Code<T>
+where
+    T: RealNum,
+{
+    fn from_node(node: &Node<T>) -> Code<T> {
+        fn helper<T: RealNum>(
+            node: &Node<T>,
             depth: Depth,
-        ) -> Vec<(CodeWord<Probability>, Depth)> {
-            let mut code: Vec<(CodeWord<Probability>, Depth)> = vec![];
+        ) -> Vec<(CodeWord<T>, Depth)> {
+            let mut code: Vec<(CodeWord<T>, Depth)> = vec![];
             match node.node_type() {
                 NodeType::Leaf(symbol) => {
                     code.push((
-                        CodeWord::new(*symbol, node.probability()),
+                        CodeWord::new(symbol.clone(), node.probability()),
                         depth,
                     ));
                 }
@@ -98,83 +226,271 @@ impl FromNode for Code<Probability> {
     }
 }
 
+#[allow(dead_code)]
 pub trait MaxDepth {
     fn max_depth(&self) -> Depth;
 }
 
-impl MaxDepth for Code<Probability> {
+impl<T> MaxDepth for Code<T> {
     fn max_depth(&self) -> Depth {
         *self.iter().max_by_key(|(_, &v)| v).unwrap().1
     }
 }
 
-fn next_length_profiles_from_previous(
-    length_profile: &SortedVec<Depth>,
-) -> Vec<SortedVec<Depth>> {
-    let mut possible_length_profiles = vec![];
-    let previous_depth = 0;
-    for &depth in length_profile.iter() {
-        if previous_depth != depth {
-            let mut length_profile = length_profile.clone();
-            length_profile.remove_item(&depth);
-            length_profile.insert(depth + 1);
-            length_profile.insert(depth + 1);
-            possible_length_profiles.push(length_profile);
+//Every length profile, as a non-decreasing list of depths, of a prefix code
+//for `num_leaves` symbols with no code word deeper than `max_depth`. With
+//`kraft_equality` only profiles whose Kraft sum is exactly one are
+//generated, which are those of full binary trees. `with_arity` counts code
+//words over a larger alphabet instead. Profiles are streamed in
+//lexicographic order by a depth first search over (depths placed so far,
+//current depth, free nodes at the current depth).
+#[derive(Clone, Debug)]
+pub struct LengthProfiles {
+    num_leaves: usize,
+    max_depth: Depth,
+    kraft_equality: bool,
+    arity: usize,
+    stack: Vec<(Vec<Depth>, Depth, usize)>,
+}
+
+impl LengthProfiles {
+    pub fn new(
+        num_leaves: usize,
+        max_depth: Depth,
+        kraft_equality: bool,
+    ) -> LengthProfiles {
+        let stack = if num_leaves > 0 {
+            vec![(Vec::with_capacity(num_leaves), 0, 1)]
+        } else {
+            vec![]
+        };
+        LengthProfiles {
+            num_leaves,
+            max_depth,
+            kraft_equality,
+            arity: 2,
+            stack,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn with_arity(self, arity: usize) -> LengthProfiles {
+        assert!(arity >= 2);
+        LengthProfiles { arity, ..self }
+    }
+
+    //Length profiles of full binary trees, which can't be deeper than
+    //num_leaves - 1
+    pub fn full_trees(num_leaves: usize) -> LengthProfiles {
+        let max_depth = num_leaves.saturating_sub(1) as Depth;
+        LengthProfiles::new(num_leaves, max_depth, true)
+    }
+}
+
+impl Iterator for LengthProfiles {
+    type Item = Vec<Depth>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((profile, depth, free)) = self.stack.pop() {
+            let remaining = self.num_leaves - profile.len();
+            if remaining == 0 {
+                if free == 0 || !self.kraft_equality {
+                    return Some(profile);
+                }
+                continue;
+            }
+            //Every free node has to be filled for Kraft equality, and only
+            //`remaining` free nodes can ever be used otherwise
+            if free == 0 || (self.kraft_equality && free > remaining) {
+                continue;
+            }
+            if depth < self.max_depth {
+                let deeper_free = if self.kraft_equality {
+                    self.arity * free
+                } else {
+                    (self.arity * free).min(remaining)
+                };
+                self.stack.push((profile.clone(), depth + 1, deeper_free));
+            }
+            let mut profile = profile;
+            profile.push(depth);
+            self.stack.push((profile, depth, free - 1));
         }
+        None
     }
-    possible_length_profiles
 }
 
-fn next_set_of_length_profiles(
-    previous_length_profiles: &HashSet<SortedVec<Depth>, Xxh3Builder>,
-) -> HashSet<SortedVec<Depth>, Xxh3Builder> {
-    let mut next_length_profiles = HashSet::with_hasher(Xxh3Builder::default());
-    for length_profile in previous_length_profiles.iter() {
-        next_length_profiles
-            .extend(next_length_profiles_from_previous(length_profile))
+//Every distinct ordering of a multiset, in lexicographic order
+#[derive(Clone, Debug)]
+pub struct DistinctPermutations<T> {
+    next: Option<Vec<T>>,
+}
+
+impl<T: Ord + Clone> DistinctPermutations<T> {
+    pub fn new(mut items: Vec<T>) -> DistinctPermutations<T> {
+        items.sort();
+        DistinctPermutations { next: Some(items) }
     }
-    next_length_profiles
 }
 
-fn permute_length_profiles(
-    length_profiles: HashSet<SortedVec<Depth>, Xxh3Builder>,
-) -> HashSet<Vec<Depth>, Xxh3Builder> {
-    fn permutations(profile: SortedVec<Depth>) -> HashSet<Vec<Depth>> {
-        let len = profile.len();
-        profile.into_vec().into_iter().permutations(len).collect()
+impl<T: Ord + Clone> Iterator for DistinctPermutations<T> {
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next.take()?;
+        let mut next = current.clone();
+        let Some(pivot) =
+            (1..next.len()).rev().find(|&i| next[i - 1] < next[i])
+        else {
+            return Some(current);
+        };
+        let swap = (pivot..next.len())
+            .rev()
+            .find(|&i| next[pivot - 1] < next[i])
+            .unwrap();
+        next.swap(pivot - 1, swap);
+        next[pivot..].reverse();
+        self.next = Some(next);
+        Some(current)
     }
-    length_profiles.into_iter().flat_map(permutations).collect()
 }
 
+//Every assignment of depths to `num_leaves` ordered symbols that forms a
+//full binary tree
+#[allow(dead_code)]
 pub fn possible_length_profiles(
     num_leaves: usize,
-) -> Option<HashSet<Vec<Depth>, Xxh3Builder>> {
-    if num_leaves < 2 {
-        return None;
+) -> impl Iterator<Item = Vec<Depth>> {
+    LengthProfiles::full_trees(num_leaves).flat_map(DistinctPermutations::new)
+}
+
+//Every candidate code for a source up to relabeling symbols of equal
+//probability, as dense codes over it. Relabeling within a class only
+//permutes which of its symbols win, tie and lose, so for every
+//non-decreasing length profile of a full binary tree there's one code per
+//way of splitting the profile between the probability classes, with each
+//class's depths given to its symbols in ascending order, and
+//`arrange_against` recovers the best labelling against a particular code.
+//Profiles are few, so the parallel iterator splits over profiles and
+//enumerates the splits of each one sequentially.
+//
+//Pruning codes that give a symbol a longer code word than a less probable
+//one would leave one code per profile. That keeps a code of minimum
+//expected length, but not necessarily a code that competitively beats a
+//given one, since a beating code may have to give up a probable symbol to
+//win two less probable ones, so it isn't done.
+#[derive(Clone, Debug)]
+pub struct CandidateCodes<T> {
+    source: DenseSource<T>,
+    profiles: Vec<Vec<Depth>>,
+    //Indices of the code words, most probable class first
+    order: Vec<usize>,
+    starts_class: Vec<bool>,
+}
+
+impl<T> CandidateCodes<T>
+where
+    T: RealNum,
+{
+    pub fn new(source: DenseSource<T>) -> CandidateCodes<T> {
+        let profiles = LengthProfiles::full_trees(source.len()).collect();
+        let probability = |i: usize| source.code_words()[i].probability();
+        let order = (0..source.len())
+            .sorted_by(|&a, &b| probability(b).cmp(probability(a)))
+            .collect_vec();
+        let starts_class = order
+            .iter()
+            .enumerate()
+            .map(|(i, &index)| {
+                i == 0 || probability(order[i - 1]) != probability(index)
+            })
+            .collect_vec();
+        CandidateCodes {
+            source,
+            profiles,
+            order,
+            starts_class,
+        }
+    }
+
+    pub fn source(&self) -> &DenseSource<T> {
+        &self.source
+    }
+
+    #[allow(dead_code)]
+    pub fn codes(&self) -> impl Iterator<Item = DenseCode> + '_ {
+        self.profiles
+            .iter()
+            .flat_map(|profile| self.splits(profile))
     }
-    let mut length_profiles: Vec<HashSet<SortedVec<Depth>, Xxh3Builder>> =
-        vec![];
-    let mut length_profiles_for_two_nodes =
-        HashSet::with_hasher(Xxh3Builder::default());
-    length_profiles_for_two_nodes.insert(SortedVec::from_unsorted(vec![1, 1]));
-    length_profiles.push(length_profiles_for_two_nodes);
-    for i in 0..num_leaves - 2 {
-        length_profiles.push(next_set_of_length_profiles(&length_profiles[i]))
-    }
-    Some(
-        length_profiles
+
+    pub fn par_codes(&self) -> impl ParallelIterator<Item = DenseCode> + '_
+    where
+        T: Sync,
+    {
+        self.profiles
+            .par_iter()
+            .flat_map_iter(|profile| self.splits(profile))
+    }
+
+    fn splits(&self, profile: &[Depth]) -> Vec<DenseCode> {
+        //Picks depths position by position, non-decreasing within each class
+        fn split(
+            depths: &[Depth],
+            available: &mut [usize],
+            starts_class: &[bool],
+            chosen: &mut Vec<usize>,
+            out: &mut Vec<Vec<usize>>,
+        ) {
+            if chosen.len() == starts_class.len() {
+                out.push(chosen.clone());
+                return;
+            }
+            let start = match chosen.last() {
+                Some(&last) if !starts_class[chosen.len()] => last,
+                _ => 0,
+            };
+            for i in start..depths.len() {
+                if available[i] == 0 {
+                    continue;
+                }
+                available[i] -= 1;
+                chosen.push(i);
+                split(depths, available, starts_class, chosen, out);
+                chosen.pop();
+                available[i] += 1;
+            }
+        }
+
+        let counts = profile.iter().copied().counts();
+        let depths = counts.keys().copied().sorted().collect_vec();
+        let mut available = depths.iter().map(|d| counts[d]).collect_vec();
+        let mut splits = vec![];
+        split(
+            &depths,
+            &mut available,
+            &self.starts_class,
+            &mut vec![],
+            &mut splits,
+        );
+        splits
             .into_iter()
-            .last()
-            .into_par_iter()
-            .flat_map(permute_length_profiles)
-            .collect(),
-    )
+            .map(|split| {
+                let mut code = vec![0; split.len()];
+                for (&index, i) in zip(&self.order, split) {
+                    code[index] = depths[i];
+                }
+                DenseCode::new(code)
+            })
+            .collect_vec()
+    }
 }
 
+#[allow(dead_code)]
 pub fn possible_codes<T>(
     source: Source<T>,
-    length_profiles: HashSet<Vec<Depth>, Xxh3Builder>,
-) -> Vec<Code<T>>
+    length_profiles: impl IntoIterator<Item = Vec<Depth>>,
+) -> impl Iterator<Item = Code<T>>
 where
     T: RealNum + Hash,
 {
@@ -187,13 +503,96 @@ where
         .collect_vec();
     length_profiles
         .into_iter()
-        .map(|profile| zip(code_words.clone(), profile).collect())
-        .collect()
+        .map(move |profile| zip(code_words.clone(), profile).collect())
+}
+
+//The depths a class has in a code, and the code words of the class with
+//their depths in the code it's arranged against
+type ClassDepths<T> = (Vec<Depth>, Vec<(Depth, CodeWord<T>)>);
+
+//Relabels `code` within each class of equal probability to maximize its
+//competitive advantage over `other`. Within a class only the number of wins
+//minus losses matters, which is the horse race of Tian Ji: win with the
+//longest code word when it's shorter than the other's longest, or with the
+//shortest when the longest ones tie and it's shorter than the other's
+//shortest, and otherwise spend the longest against the other's shortest.
+//None if the codes don't cover the same code words.
+pub fn arrange_against<T>(code: &Code<T>, other: &Code<T>) -> Option<Code<T>>
+where
+    T: RealNum,
+{
+    if code.len() != other.len() {
+        return None;
+    }
+    let mut classes: HashMap<T, ClassDepths<T>> = HashMap::new();
+    for (code_word, &depth) in code.iter() {
+        let other_depth = *other.get(code_word)?;
+        let class = classes.entry(code_word.probability.clone()).or_default();
+        class.0.push(depth);
+        class.1.push((other_depth, code_word.clone()));
+    }
+    let mut arranged = Code::new();
+    for (_, (mut ours, mut theirs)) in classes {
+        ours.sort();
+        theirs.sort_by_key(|(depth, _)| *depth);
+        let (mut our_best, mut our_worst) = (0, ours.len());
+        let (mut their_best, mut their_worst) = (0, theirs.len());
+        while our_best < our_worst {
+            let (ours_at, theirs_at) =
+                if ours[our_worst - 1] < theirs[their_worst - 1].0 {
+                    our_worst -= 1;
+                    their_worst -= 1;
+                    (our_worst, their_worst)
+                } else if ours[our_worst - 1] == theirs[their_worst - 1].0
+                    && ours[our_best] < theirs[their_best].0
+                {
+                    our_best += 1;
+                    their_best += 1;
+                    (our_best - 1, their_best - 1)
+                } else {
+                    our_worst -= 1;
+                    their_best += 1;
+                    (our_worst, their_best - 1)
+                };
+            arranged.insert(theirs[theirs_at].1.clone(), ours[ours_at]);
+        }
+    }
+    Some(arranged)
+}
+
+//Checks shared by the tests of several modules
+#[cfg(test)]
+pub mod test_helpers {
+    use super::*;
+
+    //Σ arity^(max_depth - d), the Kraft sum in units of the deepest level
+    pub fn kraft_sum(depths: &[Depth], max_depth: Depth, arity: u64) -> u128 {
+        depths
+            .iter()
+            .map(|&d| (arity as u128).pow((max_depth - d) as u32))
+            .sum()
+    }
+
+    pub fn kraft_sum_fits<T>(code: &Code<T>, arity: u64) -> bool {
+        let max_depth = code.max_depth();
+        let depths = code.values().copied().collect_vec();
+        kraft_sum(&depths, max_depth, arity)
+            <= (arity as u128).pow(max_depth as u32)
+    }
+
+    pub fn expected_length(code: &Code<Probability>) -> Probability {
+        code.iter()
+            .map(|(code_word, &depth)| code_word.probability * depth as u32)
+            .sum()
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::test_helpers::{expected_length, kraft_sum};
     use super::*;
+    use itertools::iproduct;
+    use num::{BigRational, BigUint};
 
     #[test]
     fn from_node_test() {
@@ -248,5 +647,195 @@ mod tests {
         assert!(code_b.beats(&code_a).unwrap());
         assert!(code_c.beats(&code_b).unwrap());
         assert!(code_a.beats(&code_c).unwrap());
+        assert!(code_a.loses(&code_b).unwrap());
+        assert_eq!(
+            code_b.competitive_advantage(&code_a).unwrap(),
+            Advantage { gained: 5, lost: 4 }
+        );
+
+        let mut code_d = code_a.clone();
+        code_d.remove(&CodeWord::new('a', 1));
+        assert!(code_a.competitive_advantage(&code_d).is_none());
+    }
+
+    #[test]
+    fn length_profiles_test() {
+        //A002572, partitions of 1 into n powers of 1/2
+        let counts = (1..=10)
+            .map(|n| LengthProfiles::full_trees(n).count())
+            .collect_vec();
+        assert_eq!(counts, vec![1, 1, 1, 2, 3, 5, 9, 16, 28, 50]);
+        assert_eq!(LengthProfiles::full_trees(0).count(), 0);
+        assert_eq!(
+            LengthProfiles::full_trees(5).collect_vec(),
+            vec![
+                vec![1, 2, 3, 4, 4],
+                vec![1, 3, 3, 3, 3],
+                vec![2, 2, 2, 3, 3],
+            ]
+        );
+
+        //Against brute force over every depth assignment
+        for (n, max_depth, kraft_equality, arity) in
+            iproduct!(1..=6, 0..=5, [false, true], 2..=4)
+        {
+            let expected = (0..n)
+                .map(|_| 0..=max_depth)
+                .multi_cartesian_product()
+                .filter(|profile| {
+                    let sum = kraft_sum(profile, max_depth, arity as u64);
+                    let units = (arity as u128).pow(max_depth as u32);
+                    sum == units || (!kraft_equality && sum < units)
+                })
+                .map(|profile| profile.into_iter().sorted().collect_vec())
+                .unique()
+                .sorted()
+                .collect_vec();
+            let profiles = LengthProfiles::new(n, max_depth, kraft_equality)
+                .with_arity(arity)
+                .collect_vec();
+            assert_eq!(profiles, expected);
+        }
+
+        //Full ternary trees only have an odd number of leaves
+        let counts = (1..=7)
+            .map(|n| {
+                LengthProfiles::new(n, n as Depth, true)
+                    .with_arity(3)
+                    .count()
+            })
+            .collect_vec();
+        assert_eq!(counts, vec![1, 0, 1, 0, 1, 0, 2]);
+    }
+
+    #[test]
+    fn possible_length_profiles_test() {
+        let permutations = DistinctPermutations::new(vec![2, 1, 2, 1])
+            .map(|p| p.into_iter().join(""))
+            .collect_vec();
+        assert_eq!(
+            permutations,
+            vec!["1122", "1212", "1221", "2112", "2121", "2211"]
+        );
+        assert_eq!(DistinctPermutations::new(Vec::<u8>::new()).count(), 1);
+
+        //Ordered full binary trees of depth at most n - 1 by brute force
+        for n in 2..=6 {
+            let max_depth = n as Depth - 1;
+            let expected = (0..n)
+                .map(|_| 1..=max_depth)
+                .multi_cartesian_product()
+                .filter(|profile| {
+                    kraft_sum(profile, max_depth, 2) == 1 << max_depth
+                })
+                .count();
+            let profiles = possible_length_profiles(n).collect_vec();
+            assert_eq!(profiles.len(), expected);
+            assert_eq!(profiles.iter().unique().count(), expected);
+        }
+    }
+
+    fn candidate_codes(source: Source<Probability>) -> Vec<Code<Probability>> {
+        let candidates = CandidateCodes::new(DenseSource::new(source));
+        candidates
+            .codes()
+            .map(|code| candidates.source().to_code(&code))
+            .collect_vec()
+    }
+
+    #[test]
+    fn candidate_codes_test() {
+        for weights in [
+            vec![1, 1, 1, 1, 1],
+            vec![1, 1, 2, 2, 2, 3],
+            vec![1, 2, 2, 3, 3, 3],
+            vec![1, 2, 3, 5, 8],
+            vec![2, 2, 3, 3, 4, 4],
+        ] {
+            let n = weights.len();
+            let source = Source::from_weights(weights);
+            let all =
+                possible_codes(source.clone(), possible_length_profiles(n))
+                    .collect_vec();
+            let classes = candidate_codes(source);
+            assert!(classes.len() <= all.len());
+            for code in classes.iter() {
+                assert!(all.contains(code));
+            }
+            //Arranging class representatives loses no competitive advantage
+            for code in all.iter() {
+                let best = all
+                    .iter()
+                    .map(|other| other.competitive_advantage(code).unwrap())
+                    .max()
+                    .unwrap();
+                let best_class = classes
+                    .iter()
+                    .map(|other| {
+                        arrange_against(other, code)
+                            .unwrap()
+                            .competitive_advantage(code)
+                            .unwrap()
+                    })
+                    .max()
+                    .unwrap();
+                assert_eq!(best, best_class);
+            }
+        }
+        //Five symbols of equal probability have one code per profile
+        let uniform = Source::from_weights(vec![1; 5]);
+        assert_eq!(candidate_codes(uniform).len(), 3);
+        //Distinct probabilities leave nothing to reduce
+        let distinct = Source::from_weights(vec![1, 2, 3, 5, 8]);
+        assert_eq!(
+            candidate_codes(distinct).len(),
+            possible_length_profiles(5).count()
+        );
+    }
+
+    #[test]
+    fn monotone_codes_miss_beating_codes_test() {
+        //The monotone code with depths 4,4,3,2,2,2 is beaten, but only by
+        //codes that give a more probable symbol a longer code word
+        let source = Source::from_weights(vec![12, 12, 25, 30, 30, 33]);
+        let all = possible_codes(source.clone(), possible_length_profiles(6))
+            .collect_vec();
+        let is_monotone = |code: &&Code<Probability>| {
+            code.iter().tuple_combinations().all(|((a, x), (b, y))| {
+                a.probability.cmp(&b.probability) != x.cmp(y)
+                    || a.probability == b.probability
+            })
+        };
+        let monotone = all.iter().filter(is_monotone).collect_vec();
+        assert_eq!(
+            monotone.iter().map(|code| expected_length(code)).min(),
+            all.iter().map(expected_length).min()
+        );
+        let code = monotone
+            .iter()
+            .find(|code| code.values().sorted().eq(&[2, 2, 2, 3, 4, 4]))
+            .unwrap();
+        assert!(all.iter().any(|other| other.beats(code).unwrap()));
+        assert!(!monotone.iter().any(|other| other.beats(code).unwrap()));
+    }
+
+    #[test]
+    fn generic_weights_test() {
+        fn check<T: RealNum + std::fmt::Debug>(weights: [T; 4]) {
+            let leaves = zip(['a', 'b', 'c', 'd'], weights)
+                .map(|(symbol, weight)| Node::new_leaf(weight, symbol))
+                .collect_vec();
+            let huff = Code::from_node(&Node::new_huffman(leaves).unwrap());
+            assert_eq!(huff.max_depth(), 3);
+            let mut flat = huff.clone();
+            flat.values_mut().for_each(|depth| *depth = 2);
+            assert!(huff.beats(&flat).unwrap());
+            assert!(flat.loses(&huff).unwrap());
+            assert!(huff.ties(&huff).unwrap());
+        }
+        check([1u64, 2, 4, 8]);
+        check([1u128 << 100, 1 << 101, 1 << 102, 1 << 103]);
+        check([1u32, 2, 4, 8].map(BigUint::from));
+        check([1, 2, 4, 8].map(|d| BigRational::new(1.into(), d.into())));
     }
 }
@@ -0,0 +1,302 @@
+use crate::bits::{canonical_codebook, codebook_from_node, Bits, Codebook};
+use crate::code::Code;
+use crate::node::{Node, RealNum};
+use crate::source::{Source, Symbol};
+
+use std::collections::HashMap;
+use xxhash_rust::xxh3::Xxh3Builder;
+
+//A stream of symbols packed into bytes, most significant bit first. The
+//number of symbols is kept so that a one symbol code, whose only code word
+//is empty, still decodes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Encoded {
+    bytes: Vec<u8>,
+    bits: usize,
+    symbols: usize,
+}
+
+impl Encoded {
+    #[allow(dead_code)]
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn bits(&self) -> usize {
+        self.bits
+    }
+
+    #[allow(dead_code)]
+    pub fn symbols(&self) -> usize {
+        self.symbols
+    }
+
+    fn push(&mut self, bit: bool) {
+        if self.bits.is_multiple_of(8) {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 0x80 >> (self.bits % 8);
+        }
+        self.bits += 1;
+    }
+
+    fn bit(&self, i: usize) -> bool {
+        self.bytes[i / 8] & (0x80 >> (i % 8)) != 0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Encoder {
+    code_words: HashMap<Symbol, Bits, Xxh3Builder>,
+}
+
+impl Encoder {
+    pub fn new<T>(codebook: &Codebook<T>) -> Encoder {
+        Encoder {
+            code_words: codebook
+                .iter()
+                .map(|(code_word, bits)| {
+                    (code_word.source_symbol().clone(), bits.clone())
+                })
+                .collect(),
+        }
+    }
+
+    //Huffman's code for the source, as read off the tree
+    #[allow(dead_code)]
+    pub fn huffman<T>(source: &Source<T>) -> Option<Encoder>
+    where
+        T: RealNum,
+    {
+        let tree = Node::new_huffman(source.to_leaves_vec())?;
+        Some(Encoder::new(&codebook_from_node(&tree)))
+    }
+
+    //The canonical code words for the code's lengths, None if it breaks
+    //Kraft's inequality
+    pub fn canonical<T>(code: &Code<T>) -> Option<Encoder>
+    where
+        T: RealNum,
+    {
+        Some(Encoder::new(&canonical_codebook(code)?))
+    }
+
+    //None if a symbol has no code word
+    pub fn encode<S: Into<Symbol>>(
+        &self,
+        symbols: impl IntoIterator<Item = S>,
+    ) -> Option<Encoded> {
+        let mut encoded = Encoded {
+            bytes: vec![],
+            bits: 0,
+            symbols: 0,
+        };
+        for symbol in symbols {
+            for &bit in self.code_words.get(&symbol.into())? {
+                encoded.push(bit);
+            }
+            encoded.symbols += 1;
+        }
+        Some(encoded)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum TrieNode {
+    Branch([Option<usize>; 2]),
+    Leaf(Symbol),
+}
+
+//Walks a binary trie of the code words, one bit at a time
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    trie: Vec<TrieNode>,
+}
+
+impl Decoder {
+    //None if the code words aren't prefix free
+    pub fn new<T>(codebook: &Codebook<T>) -> Option<Decoder> {
+        let mut trie = vec![TrieNode::Branch([None, None])];
+        for (code_word, bits) in codebook.iter() {
+            let mut node = 0;
+            for &bit in bits.iter() {
+                let next = trie.len();
+                let TrieNode::Branch(children) = &mut trie[node] else {
+                    return None;
+                };
+                node = *children[bit as usize].get_or_insert(next);
+                if node == next {
+                    trie.push(TrieNode::Branch([None, None]));
+                }
+            }
+            match &trie[node] {
+                TrieNode::Branch([None, None]) => {
+                    let symbol = code_word.source_symbol().clone();
+                    trie[node] = TrieNode::Leaf(symbol);
+                }
+                _ => return None,
+            }
+        }
+        Some(Decoder { trie })
+    }
+
+    #[allow(dead_code)]
+    pub fn canonical<T>(code: &Code<T>) -> Option<Decoder>
+    where
+        T: RealNum,
+    {
+        Decoder::new(&canonical_codebook(code)?)
+    }
+
+    //None if the bits run out mid code word, take a path no code word
+    //does, or are left over after the last symbol
+    pub fn decode(&self, encoded: &Encoded) -> Option<Vec<Symbol>> {
+        let mut symbols = Vec::with_capacity(encoded.symbols);
+        let mut i = 0;
+        while symbols.len() < encoded.symbols {
+            let mut node = 0;
+            loop {
+                match &self.trie[node] {
+                    TrieNode::Leaf(symbol) => {
+                        symbols.push(symbol.clone());
+                        break;
+                    }
+                    TrieNode::Branch(children) => {
+                        if i == encoded.bits {
+                            return None;
+                        }
+                        node = children[encoded.bit(i) as usize]?;
+                        i += 1;
+                    }
+                }
+            }
+        }
+        (i == encoded.bits).then_some(symbols)
+    }
+}
+
+//What the code costs on the stream in bits, Σ count·depth, without
+//encoding anything. None if a symbol isn't in the code.
+#[allow(dead_code)]
+pub fn compressed_bits<T, S>(
+    code: &Code<T>,
+    symbols: impl IntoIterator<Item = S>,
+) -> Option<usize>
+where
+    T: RealNum,
+    S: Into<Symbol>,
+{
+    let depths: HashMap<&Symbol, usize, Xxh3Builder> = code
+        .iter()
+        .map(|(code_word, &depth)| (code_word.source_symbol(), depth as usize))
+        .collect();
+    symbols
+        .into_iter()
+        .map(|symbol| depths.get(&symbol.into()).copied())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::{CodeWord, CompetitiveOrd, FromNode};
+    use crate::optimality::beating_code;
+    use itertools::Itertools;
+
+    #[test]
+    fn encode_decode_test() {
+        let text = "abracadabra alakazam";
+        let source = Source::from_chars(text).unwrap();
+        let tree = Node::new_huffman(source.to_leaves_vec()).unwrap();
+        let codebook = codebook_from_node(&tree);
+        let encoder = Encoder::new(&codebook);
+        let encoded = encoder.encode(text.chars()).unwrap();
+        assert_eq!(encoded.symbols(), text.len());
+        assert_eq!(encoded.bytes().len(), encoded.bits().div_ceil(8));
+
+        let code = Code::from_node(&tree);
+        assert_eq!(compressed_bits(&code, text.chars()), Some(encoded.bits()));
+        assert_eq!(
+            Encoder::huffman(&source).unwrap().encode(text.chars()),
+            Some(encoded.clone())
+        );
+
+        let decoded = Decoder::new(&codebook).unwrap().decode(&encoded);
+        let expected = text.chars().map(Symbol::from).collect_vec();
+        assert_eq!(decoded, Some(expected.clone()));
+
+        //The canonical code words have the same lengths
+        let canonical = Encoder::canonical(&code).unwrap();
+        let encoded = canonical.encode(text.chars()).unwrap();
+        assert_eq!(compressed_bits(&code, text.chars()), Some(encoded.bits()));
+        let decoded = Decoder::canonical(&code).unwrap().decode(&encoded);
+        assert_eq!(decoded, Some(expected));
+
+        assert!(encoder.encode("abq".chars()).is_none());
+        assert_eq!(compressed_bits(&code, "abq".chars()), None);
+    }
+
+    #[test]
+    fn decode_errors_test() {
+        let source = Source::from_chars("aabbbc").unwrap();
+        let codebook = codebook_from_node(
+            &Node::new_huffman(source.to_leaves_vec()).unwrap(),
+        );
+        let encoder = Encoder::new(&codebook);
+        let decoder = Decoder::new(&codebook).unwrap();
+        let mut encoded = encoder.encode("abc".chars()).unwrap();
+        encoded.symbols += 1;
+        assert!(decoder.decode(&encoded).is_none());
+        encoded.symbols -= 2;
+        assert!(decoder.decode(&encoded).is_none());
+
+        let mut not_prefix_free = codebook.clone();
+        let (code_word, bits) = not_prefix_free.iter().next().unwrap();
+        let (code_word, mut bits) = (code_word.clone(), bits.clone());
+        bits.push(false);
+        not_prefix_free.insert(CodeWord::new('z', 1), bits);
+        assert!(Decoder::new(&not_prefix_free).is_none());
+        not_prefix_free.remove(&code_word);
+        assert!(Decoder::new(&not_prefix_free).is_some());
+
+        //A lone symbol has the empty code word
+        let single = Source::from_chars("aaaa").unwrap();
+        let codebook = codebook_from_node(
+            &Node::new_huffman(single.to_leaves_vec()).unwrap(),
+        );
+        let encoded = Encoder::new(&codebook).encode("aaaa".chars()).unwrap();
+        assert_eq!(encoded.bits(), 0);
+        let decoded = Decoder::new(&codebook).unwrap().decode(&encoded);
+        assert_eq!(decoded.map(|symbols| symbols.len()), Some(4));
+    }
+
+    #[test]
+    fn beating_code_compresses_test() {
+        //A code that beats Huffman's on the source still can't be shorter
+        //on the stream the source counts
+        let text: String = [('a', 12), ('b', 12), ('c', 25), ('d', 30)]
+            .into_iter()
+            .chain([('e', 30), ('f', 33)])
+            .map(|(c, count)| c.to_string().repeat(count))
+            .collect();
+        let text = text.as_str();
+        let source = Source::from_chars(text).unwrap();
+        let huffman = Code::from_node(
+            &Node::new_huffman(source.to_leaves_vec()).unwrap(),
+        );
+        let better = beating_code(&huffman).unwrap();
+        assert!(better.beats(&huffman).unwrap());
+        let huffman_bits = Encoder::canonical(&huffman)
+            .unwrap()
+            .encode(text.chars())
+            .unwrap()
+            .bits();
+        let better_bits = Encoder::canonical(&better)
+            .unwrap()
+            .encode(text.chars())
+            .unwrap()
+            .bits();
+        assert!(huffman_bits < better_bits);
+    }
+}
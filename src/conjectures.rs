@@ -1,156 +1,160 @@
-use crate::code::{
-    possible_codes, possible_length_profiles, Code, CompetitiveOrd, FromNode,
-    MaxDepth,
-};
-use crate::node::{Node, RealNum};
-use crate::source::Source;
+use crate::code::{Code, CompetitiveOrd, FromNode};
+use crate::dense::DenseSource;
+use crate::node::{Node, TieBreak};
+use crate::optimality::beating_code;
+use crate::reduction::distinct_huffman_trees;
+use crate::report::Comparison;
+use crate::source::{Source, SourceSeed};
 
 use itertools::Itertools;
-use std::iter::{once, repeat, zip};
+use std::iter::zip;
+use std::sync::OnceLock;
 
-fn remove_two<T>(x: usize, y: usize, vec: &mut Vec<T>) -> (T, T) {
-    assert!(x != y);
-    let (first, second) = if x < y { (x, y) } else { (y, x) };
+type HuffmanTreesAndCodes = (Vec<Node<u32>>, Vec<Code<u32>>);
 
-    let j = vec.remove(first);
-    let k = vec.remove(second - 1);
-    (j, k)
+fn is_competitively_optimal(code: &Code<u32>) -> bool {
+    beating_code(code).is_none()
 }
 
-fn join_nodes_by_indices<T>(
-    pair_index: (usize, usize),
-    mut nodes: Vec<Node<T>>,
-) -> Vec<Node<T>>
-where
-    T: RealNum,
-{
-    let (left, right) = remove_two(pair_index.0, pair_index.1, &mut nodes);
-    nodes.push(Node::new_branch(left, right));
-    nodes
+//Huffman codes of fewer symbols all tie: they differ only by swapping
+//symbols of equal weight, or by trading a depth 1 code word for two depth 3
+//ones whose weights add up to it, so no conjecture applies to them
+pub const MIN_APPLICABLE_SIZE: usize = 5;
+
+//A sampled source together with its distinct Huffman trees and their codes,
+//which are computed at most once and shared between `applies_to` and `check`
+pub struct Sample {
+    //None for sources that weren't randomly sampled
+    pub source_seed: Option<SourceSeed>,
+    pub source: Source<u32>,
+    huffman: OnceLock<HuffmanTreesAndCodes>,
 }
 
-fn possible_reductions<T>(mut nodes: Vec<Node<T>>) -> Vec<Vec<Node<T>>>
-where
-    T: RealNum,
-{
-    //TODO: Add better error handling
-    assert!(!nodes.is_empty());
-    nodes.sort();
-    let num_smallest_nodes =
-        nodes.iter().take_while(|&node| *node == nodes[0]).count();
-    let num_second_smallest_nodes = nodes[num_smallest_nodes..]
-        .iter()
-        .take_while(|&node| *node == nodes[num_smallest_nodes])
-        .count();
-
-    let possible_pair_indices: Box<dyn Iterator<Item = (usize, usize)>> =
-        match (num_smallest_nodes, num_second_smallest_nodes) {
-            (0, _) => panic!("Impossible because of the length assertion"),
-            (1, 0) => panic!("Impossible because of the length assertion"),
-            (1, 1) => Box::new(once((0, 1))),
-            (1, n) => Box::new(zip(repeat(0usize), 1..(n + 1))),
-            (n, _) => Box::new((0..n).tuple_combinations::<(_, _)>()),
-        };
+impl Sample {
+    pub fn new(source_seed: Option<SourceSeed>, source: Source<u32>) -> Sample {
+        Sample {
+            source_seed,
+            source,
+            huffman: OnceLock::new(),
+        }
+    }
 
-    possible_pair_indices
-        .map(|pair| join_nodes_by_indices(pair, nodes.clone()))
-        .collect()
-}
+    fn huffman(&self) -> &HuffmanTreesAndCodes {
+        self.huffman.get_or_init(|| {
+            let trees = distinct_huffman_trees(self.source.to_leaves_vec());
+            let codes = trees.iter().map(Code::from_node).collect_vec();
+            (trees, codes)
+        })
+    }
 
-//Note that this breaks if there exists a zero probablity element
-fn all_possible_reductions<T>(nodes: Vec<Node<T>>) -> Vec<Node<T>>
-where
-    T: RealNum,
-{
-    let mut partial_reductions = vec![nodes];
-    let mut completed_reductions: Vec<_> = vec![];
-    while !partial_reductions.is_empty() {
-        (completed_reductions, partial_reductions) = partial_reductions
-            .into_iter()
-            .partition(|nodes| nodes.len() < 2);
-        partial_reductions = partial_reductions
-            .into_iter()
-            .flat_map(|nodes_list| possible_reductions(nodes_list))
-            .collect_vec();
+    pub fn huffman_trees(&self) -> &[Node<u32>] {
+        &self.huffman().0
     }
-    completed_reductions.into_iter().flatten().collect_vec()
-}
 
-#[allow(dead_code)]
-pub fn no_huffman_code_competitively_dominates_skinniest(
-    source_size: usize,
-    sources_to_test: u32,
-) -> bool {
-    let mut sources_tested: u32 = 0;
-    while sources_tested < sources_to_test {
-        let leaves = Source::new(source_size).to_leaves_vec();
-        let huffman_codes: Vec<Code<u32>> = all_possible_reductions(leaves)
+    pub fn huffman_codes(&self) -> &[Code<u32>] {
+        &self.huffman().1
+    }
+
+    //Whether the source has two Huffman codes that don't tie
+    pub fn some_huffman_beats_another(&self) -> bool {
+        let codes = self.huffman_codes();
+        let Some(first) = codes.first() else {
+            return false;
+        };
+        let source = DenseSource::from_code(first);
+        let codes = codes
             .iter()
-            .map(Code::from_node)
+            .map(|code| source.to_dense(code).unwrap())
             .collect_vec();
-        match huffman_codes.len() {
-            0 => panic!("There should always exist a huffman code"),
-            1 => continue,
-            _ => (),
-        }
-        let one_huffman_dominates_other = huffman_codes
+        codes
             .iter()
             .tuple_combinations::<(_, _)>()
-            .any(|(a, b)| !a.ties(b).unwrap());
-        if one_huffman_dominates_other {
-            //println!("Found pmf where one huffman code dominates another");
-            sources_tested += 1;
-        } else {
-            continue;
-        };
-        let tallest_huffman_code: &Code<u32> = huffman_codes
-            .iter()
-            .max_by(|a, b| a.max_depth().cmp(&b.max_depth()))
-            .unwrap();
-        let better_codes = huffman_codes
-            .iter()
-            .filter(|&a| a.beats(tallest_huffman_code).unwrap())
-            .collect_vec();
-        if !better_codes.is_empty() {
-            println!("Found a code that beats skinny code");
-            dbg!(tallest_huffman_code);
-            dbg!(better_codes[0]);
-            return false;
-        }
+            .any(|(a, b)| !source.ties(a, b))
     }
-    true
 }
 
-pub fn no_huffman_dominates_another_and_is_optimal(
-    source_size: usize,
-    num_sources: u32,
-) -> bool {
-    let mut sources_tested = 0;
-    let mut sources_that_passed_heuristic = 0;
-    let possible_length_profiles =
-        possible_length_profiles(source_size).unwrap();
-    while sources_tested < num_sources {
-        let source = Source::new(source_size);
-        let leaves = source.to_leaves_vec();
-        let possible_reductions = all_possible_reductions(leaves);
-        let huffman_codes = possible_reductions
+#[derive(Debug)]
+pub struct Witness {
+    pub tree: Node<u32>,
+    pub code: Code<u32>,
+    pub comparisons: Vec<Comparison>,
+}
+
+#[derive(Debug)]
+pub enum Outcome {
+    Pass,
+    Skip,
+    Counterexample(Box<Witness>),
+}
+
+pub trait Conjecture: Sync {
+    fn name(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    //Sources this returns false for are counted as skipped, not tested
+    fn applies_to(&self, sample: &Sample) -> bool;
+    fn check(&self, sample: &Sample) -> Outcome;
+}
+
+pub struct SkinniestHuffmanUnbeaten;
+
+impl Conjecture for SkinniestHuffmanUnbeaten {
+    fn name(&self) -> &'static str {
+        "no_huffman_code_competitively_dominates_skinniest"
+    }
+
+    fn description(&self) -> &'static str {
+        "No Huffman code beats the skinniest Huffman code"
+    }
+
+    fn applies_to(&self, sample: &Sample) -> bool {
+        sample.some_huffman_beats_another()
+    }
+
+    fn check(&self, sample: &Sample) -> Outcome {
+        let tallest_huffman_tree = Node::new_huffman_with(
+            sample.source.to_leaves_vec(),
+            TieBreak::Skinniest,
+        )
+        .unwrap();
+        let tallest_huffman_code = Code::from_node(&tallest_huffman_tree);
+        let comparisons = sample
+            .huffman_codes()
             .iter()
-            .map(|node| (node, Code::from_node(node)))
+            .filter(|&a| a.beats(&tallest_huffman_code).unwrap())
+            .map(|better_code| {
+                Comparison::new(&tallest_huffman_code, better_code)
+            })
             .collect_vec();
-        match huffman_codes.len() {
-            0 => panic!("There should always exist a huffman code"),
-            1 => continue,
-            _ => (),
-        }
-        let some_huffman_beat_others = huffman_codes
-            .iter()
-            .tuple_combinations::<(_, _)>()
-            .any(|((_, code_a), (_, code_b))| !code_a.ties(code_b).unwrap());
-        if some_huffman_beat_others {
-            sources_tested += 1
-        } else {
-            continue;
+        if comparisons.is_empty() {
+            return Outcome::Pass;
         }
+        Outcome::Counterexample(Box::new(Witness {
+            tree: tallest_huffman_tree,
+            code: tallest_huffman_code,
+            comparisons,
+        }))
+    }
+}
+
+pub struct DominatingHuffmanNotOptimal;
+
+impl Conjecture for DominatingHuffmanNotOptimal {
+    fn name(&self) -> &'static str {
+        "no_huffman_dominates_another_and_is_optimal"
+    }
+
+    fn description(&self) -> &'static str {
+        "If one Huffman code beats another, no Huffman code is \
+         competitively optimal"
+    }
+
+    fn applies_to(&self, sample: &Sample) -> bool {
+        sample.source.len() >= 2 && sample.some_huffman_beats_another()
+    }
+
+    fn check(&self, sample: &Sample) -> Outcome {
+        let huffman_codes =
+            zip(sample.huffman_trees(), sample.huffman_codes()).collect_vec();
         let unbeaten_huffman_codes =
             huffman_codes.iter().filter(|(_, code)| {
                 huffman_codes
@@ -158,66 +162,78 @@ pub fn no_huffman_dominates_another_and_is_optimal(
                     .all(|(_, other_code)| !other_code.beats(code).unwrap())
             });
         let mut possibly_optimal_codes = unbeaten_huffman_codes
-            .filter(|(tree, _)| tree.is_probably_competitively_optimal())
-            .map(|(_, code)| code);
-        if possibly_optimal_codes.clone().next().is_some() {
-            sources_that_passed_heuristic += 1;
-        }
+            .filter(|(tree, _)| tree.is_probably_competitively_optimal());
 
-        let possible_codes =
-            possible_codes(source.clone(), possible_length_profiles.clone());
-        let true_optimal_code_exists = possibly_optimal_codes.any(|code| {
-            possible_codes.iter().all(|other_code| {
-                code.competitive_advantage(other_code).unwrap() <= 0
-            })
-        });
-        if true_optimal_code_exists {
-            println!("Found counterexample!!");
-            return false;
+        let true_optimal_code = possibly_optimal_codes
+            .find(|(_, code)| is_competitively_optimal(code));
+        match true_optimal_code {
+            None => Outcome::Pass,
+            Some((optimal_tree, optimal_code)) => {
+                let comparisons = huffman_codes
+                    .iter()
+                    .map(|(_, code)| Comparison::new(optimal_code, code))
+                    .collect_vec();
+                Outcome::Counterexample(Box::new(Witness {
+                    tree: (*optimal_tree).clone(),
+                    code: (*optimal_code).clone(),
+                    comparisons,
+                }))
+            }
         }
     }
-    dbg!(sources_tested);
-    dbg!(sources_that_passed_heuristic);
-    true
+}
+
+pub fn conjectures() -> Vec<Box<dyn Conjecture>> {
+    vec![
+        Box::new(SkinniestHuffmanUnbeaten),
+        Box::new(DominatingHuffmanNotOptimal),
+    ]
+}
+
+pub fn find_conjecture(name: &str) -> Option<Box<dyn Conjecture>> {
+    conjectures()
+        .into_iter()
+        .find(|conjecture| conjecture.name() == name)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::node::Node;
 
     #[test]
-    fn all_possible_reductions_test() {
-        let leaves = vec![
-            Node::new_leaf(1, 'a'),
-            Node::new_leaf(1, 'b'),
-            Node::new_leaf(2, 'c'),
-            Node::new_leaf(2, 'd'),
-        ];
-        let huff_a = Node::new_branch(
-            leaves[3].clone(),
-            Node::new_branch(
-                leaves[2].clone(),
-                Node::new_branch(leaves[0].clone(), leaves[1].clone()),
-            ),
-        );
-        let huff_b = Node::new_branch(
-            leaves[2].clone(),
-            Node::new_branch(
-                leaves[3].clone(),
-                Node::new_branch(leaves[0].clone(), leaves[1].clone()),
-            ),
-        );
+    fn find_conjecture_test() {
+        for conjecture in conjectures() {
+            let found = find_conjecture(conjecture.name()).unwrap();
+            assert_eq!(found.name(), conjecture.name());
+        }
+        assert!(find_conjecture("not_a_conjecture").is_none());
+    }
 
-        let huff_c = Node::new_branch(
-            Node::new_branch(leaves[3].clone(), leaves[2].clone()),
-            Node::new_branch(leaves[0].clone(), leaves[1].clone()),
+    #[test]
+    fn skinniest_huffman_unbeaten_test() {
+        //Huffman codes are 1,2,3,3 or 2,2,2,2, which all tie each other
+        let tied = Sample::new(
+            None,
+            Source::from_vec(vec![('a', 1), ('b', 1), ('c', 2), ('d', 2)]),
         );
+        assert!(!SkinniestHuffmanUnbeaten.applies_to(&tied));
 
-        let reductions = all_possible_reductions(leaves);
-        assert!(reductions.len() >= 1 && reductions.len() <= 4 * 3 * 2);
-        assert!(reductions.iter().any(|node| node.is_same_as(&huff_a)));
-        assert!(reductions.iter().any(|node| node.is_same_as(&huff_b)));
-        assert!(reductions.iter().any(|node| node.is_same_as(&huff_c)));
+        //Some Huffman codes beat others, but none of them beats a code of
+        //maximum depth
+        let dominated = Sample::new(
+            None,
+            Source::from_vec(vec![
+                ('a', 1),
+                ('b', 1),
+                ('c', 1),
+                ('d', 2),
+                ('e', 3),
+            ]),
+        );
+        assert!(SkinniestHuffmanUnbeaten.applies_to(&dominated));
+        assert!(matches!(
+            SkinniestHuffmanUnbeaten.check(&dominated),
+            Outcome::Pass
+        ));
     }
 }
@@ -0,0 +1,241 @@
+use crate::code::{Code, CodeWord, Depth, New};
+use crate::node::RealNum;
+use crate::source::Symbol;
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+//Trees over an alphabet of more than two letters. Codes read off these are
+//plain `Code`s, so CompetitiveOrd compares them the same way as binary
+//codes; only which depths are achievable depends on the arity.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum DaryNodeType<T>
+where
+    T: RealNum,
+{
+    Leaf(Symbol),
+    //Zero weight padding that lets Huffman's algorithm always merge
+    //`arity` nodes, left out of the code
+    Dummy,
+    Branch(Arc<[DaryNode<T>]>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaryNode<T>
+where
+    T: RealNum,
+{
+    probability: T,
+    node_type: DaryNodeType<T>,
+}
+
+impl<T> DaryNode<T>
+where
+    T: RealNum,
+{
+    pub fn new_leaf<S: Into<Symbol>>(probability: T, symbol: S) -> DaryNode<T> {
+        DaryNode {
+            probability,
+            node_type: DaryNodeType::Leaf(symbol.into()),
+        }
+    }
+    pub fn new_dummy() -> DaryNode<T> {
+        DaryNode {
+            probability: T::zero(),
+            node_type: DaryNodeType::Dummy,
+        }
+    }
+    pub fn new_branch(children: Vec<DaryNode<T>>) -> DaryNode<T> {
+        DaryNode {
+            probability: children
+                .iter()
+                .fold(T::zero(), |sum, child| sum + child.probability()),
+            node_type: DaryNodeType::Branch(children.into()),
+        }
+    }
+    pub fn probability(&self) -> T {
+        self.probability.clone()
+    }
+    #[allow(dead_code)]
+    pub fn node_type(&self) -> &DaryNodeType<T> {
+        &self.node_type
+    }
+
+    //Huffman's algorithm merging the `arity` lightest nodes at a time. So
+    //that the last merge is full, enough dummies are added first for
+    //(n - 1) to be a multiple of (arity - 1).
+    pub fn new_huffman(
+        mut nodes: Vec<DaryNode<T>>,
+        arity: usize,
+    ) -> Option<DaryNode<T>> {
+        assert!(arity >= 2);
+        if nodes.len() > 1 {
+            let dummies =
+                (arity - 1 - (nodes.len() - 1) % (arity - 1)) % (arity - 1);
+            nodes.extend(
+                std::iter::repeat_with(DaryNode::new_dummy).take(dummies),
+            );
+        }
+        while nodes.len() > 1 {
+            nodes.sort_by(|a, b| b.probability.cmp(&a.probability));
+            let children = nodes.split_off(nodes.len() - arity);
+            nodes.push(DaryNode::new_branch(children));
+        }
+        nodes.pop()
+    }
+
+    pub fn code(&self) -> Code<T> {
+        fn helper<T: RealNum>(
+            node: &DaryNode<T>,
+            depth: Depth,
+            code: &mut Code<T>,
+        ) {
+            match &node.node_type {
+                DaryNodeType::Leaf(symbol) => {
+                    code.insert(
+                        CodeWord::new(symbol.clone(), node.probability()),
+                        depth,
+                    );
+                }
+                DaryNodeType::Dummy => {}
+                DaryNodeType::Branch(children) => {
+                    for child in children.iter() {
+                        helper(child, depth + 1, code);
+                    }
+                }
+            }
+        }
+        let mut code = Code::new();
+        helper(self, 0, &mut code);
+        code
+    }
+
+    #[allow(dead_code)]
+    pub fn height(&self) -> usize {
+        match &self.node_type {
+            DaryNodeType::Leaf(_) | DaryNodeType::Dummy => 0,
+            DaryNodeType::Branch(children) => {
+                children.iter().map(DaryNode::height).max().unwrap_or(0) + 1
+            }
+        }
+    }
+}
+
+//Leaves for every symbol of a source, to build D-ary trees from
+pub fn dary_leaves<T>(
+    source: impl IntoIterator<Item = (Symbol, T)>,
+) -> Vec<DaryNode<T>>
+where
+    T: RealNum,
+{
+    source
+        .into_iter()
+        .map(|(symbol, p)| DaryNode::new_leaf(p, symbol))
+        .collect_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::test_helpers::{expected_length, kraft_sum_fits};
+    use crate::code::{
+        CompetitiveOrd, DistinctPermutations, FromNode, LengthProfiles,
+    };
+    use crate::dense::DenseSource;
+    use crate::node::Node;
+    use crate::optimality::beating_dary_code;
+    use crate::source::Source;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::iter::zip;
+
+    //Every code over the source with no code word deeper than max_depth
+    fn dary_codes(
+        source: &Source<u32>,
+        max_depth: Depth,
+        arity: usize,
+    ) -> Vec<Code<u32>> {
+        let code_words = source
+            .clone()
+            .map(|(symbol, p)| CodeWord::new(symbol, p))
+            .collect_vec();
+        LengthProfiles::new(source.len(), max_depth, false)
+            .with_arity(arity)
+            .flat_map(DistinctPermutations::new)
+            .map(|depths| zip(code_words.iter().cloned(), depths).collect())
+            .collect_vec()
+    }
+
+    #[test]
+    fn new_huffman_test() {
+        let source = Source::from_weights(vec![5, 4, 3, 2, 1, 1]);
+        let tree = DaryNode::new_huffman(dary_leaves(source), 3).unwrap();
+        assert_eq!(tree.probability(), 16);
+        let mut depths = tree.code().into_values().collect_vec();
+        depths.sort();
+        //One dummy pads the first merge of 1, 1 and 0
+        assert_eq!(depths, vec![1, 1, 2, 2, 3, 3]);
+        assert_eq!(tree.height(), 3);
+
+        let single =
+            DaryNode::new_huffman(dary_leaves(vec![('a'.into(), 1)]), 4);
+        assert_eq!(single.unwrap().code().into_values().collect_vec(), vec![0]);
+        assert!(DaryNode::<u32>::new_huffman(vec![], 3).is_none());
+
+        //Huffman's algorithm is optimal for every arity
+        let mut rng = StdRng::seed_from_u64(19);
+        for size in 2..=7 {
+            let source = Source::new(size, &mut rng);
+            let binary = Code::from_node(
+                &Node::new_huffman(source.to_leaves_vec()).unwrap(),
+            );
+            for arity in 2..=4 {
+                let tree =
+                    DaryNode::new_huffman(dary_leaves(source.clone()), arity)
+                        .unwrap();
+                let code = tree.code();
+                assert_eq!(code.len(), size);
+                assert!(kraft_sum_fits(&code, arity as u64));
+                let best = dary_codes(&source, size as Depth - 1, arity)
+                    .iter()
+                    .map(expected_length)
+                    .min()
+                    .unwrap();
+                assert_eq!(expected_length(&code), best);
+                if arity == 2 {
+                    assert_eq!(
+                        expected_length(&code),
+                        expected_length(&binary)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn beating_dary_code_test() {
+        let mut rng = StdRng::seed_from_u64(190);
+        for (size, arity) in [(3, 3), (4, 3), (5, 3), (4, 4), (5, 4)] {
+            let source = Source::new(size, &mut rng);
+            let dense = DenseSource::new(source.clone());
+            let codes = dary_codes(&source, size as Depth - 1, arity);
+            let candidates = dary_codes(&source, size as Depth + 2, arity)
+                .iter()
+                .map(|code| dense.to_dense(code).unwrap())
+                .collect_vec();
+            for code in codes.iter().step_by(codes.len() / 40 + 1) {
+                let dense_code = dense.to_dense(code).unwrap();
+                let beaten = candidates
+                    .iter()
+                    .any(|candidate| dense.beats(candidate, &dense_code));
+                let beater = beating_dary_code(code, arity as u64);
+                assert_eq!(beater.is_some(), beaten, "{:?}", code);
+                if let Some(beater) = beater {
+                    assert!(beater.beats(code).unwrap());
+                    assert!(kraft_sum_fits(&beater, arity as u64));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use crate::code::{Advantage, Code, CodeWord, Depth};
+use crate::node::RealNum;
+use crate::source::Source;
+
+use itertools::Itertools;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::iter::zip;
+use xxhash_rust::xxh3::Xxh3Builder;
+
+//The code words of a source indexed 0..n in order of symbol, with their
+//probabilities stored once, so that codes over the source are plain arrays
+//of depths
+#[derive(Clone, Debug)]
+pub struct DenseSource<T> {
+    code_words: Vec<CodeWord<T>>,
+    probabilities: Vec<T>,
+    indices: HashMap<CodeWord<T>, usize, Xxh3Builder>,
+}
+
+//Depths indexed like the code words of a DenseSource
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DenseCode(Box<[Depth]>);
+
+impl DenseCode {
+    pub fn new(depths: Vec<Depth>) -> DenseCode {
+        DenseCode(depths.into_boxed_slice())
+    }
+
+    pub fn depths(&self) -> &[Depth] {
+        &self.0
+    }
+}
+
+impl<T> DenseSource<T>
+where
+    T: RealNum,
+{
+    #[allow(dead_code)]
+    pub fn new(source: Source<T>) -> DenseSource<T> {
+        DenseSource::from_code_words(
+            source.into_iter().map(|(s, p)| CodeWord::new(s, p)),
+        )
+    }
+
+    //The source a code is for
+    pub fn from_code(code: &Code<T>) -> DenseSource<T> {
+        DenseSource::from_code_words(code.keys().cloned())
+    }
+
+    fn from_code_words(
+        code_words: impl Iterator<Item = CodeWord<T>>,
+    ) -> DenseSource<T> {
+        let code_words = code_words
+            .sorted_by(|a, b| a.source_symbol().cmp(b.source_symbol()))
+            .collect_vec();
+        let probabilities = code_words
+            .iter()
+            .map(|code_word| code_word.probability().clone())
+            .collect_vec();
+        let indices = code_words.iter().cloned().zip(0..).collect();
+        DenseSource {
+            code_words,
+            probabilities,
+            indices,
+        }
+    }
+
+    pub fn code_words(&self) -> &[CodeWord<T>] {
+        &self.code_words
+    }
+
+    pub fn len(&self) -> usize {
+        self.code_words.len()
+    }
+
+    //None if the code doesn't cover exactly this source's code words
+    pub fn to_dense(&self, code: &Code<T>) -> Option<DenseCode> {
+        if code.len() != self.len() {
+            return None;
+        }
+        let mut depths = vec![0; self.len()];
+        for (code_word, &depth) in code.iter() {
+            depths[*self.indices.get(code_word)?] = depth;
+        }
+        Some(DenseCode::new(depths))
+    }
+
+    pub fn to_code(&self, code: &DenseCode) -> Code<T> {
+        zip(
+            self.code_words.iter().cloned(),
+            code.depths().iter().copied(),
+        )
+        .collect()
+    }
+
+    //Selects instead of branching on each comparison, so for primitive
+    //probabilities the loop compiles to vector compares and masked adds
+    pub fn competitive_advantage(
+        &self,
+        code: &DenseCode,
+        other: &DenseCode,
+    ) -> Advantage<T> {
+        let zero = T::zero();
+        let mut advantage = Advantage::zero();
+        for (p, (depth, other_depth)) in zip(
+            self.probabilities.iter(),
+            zip(code.depths(), other.depths()),
+        ) {
+            let gained = if depth < other_depth { p } else { &zero };
+            let lost = if depth > other_depth { p } else { &zero };
+            advantage.gained = advantage.gained + gained.clone();
+            advantage.lost = advantage.lost + lost.clone();
+        }
+        advantage
+    }
+
+    #[allow(dead_code)]
+    pub fn beats(&self, code: &DenseCode, other: &DenseCode) -> bool {
+        self.competitive_advantage(code, other).signum() == Ordering::Greater
+    }
+
+    pub fn ties(&self, code: &DenseCode, other: &DenseCode) -> bool {
+        self.competitive_advantage(code, other).signum() == Ordering::Equal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::{
+        possible_codes, possible_length_profiles, CompetitiveOrd,
+    };
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn dense_code_test() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let source = Source::new(6, &mut rng);
+        let dense = DenseSource::new(source.clone());
+        let codes = possible_codes(source, possible_length_profiles(6))
+            .take(200)
+            .collect_vec();
+        let dense_codes = codes
+            .iter()
+            .map(|code| dense.to_dense(code).unwrap())
+            .collect_vec();
+        for (code, dense_code) in zip(codes.iter(), dense_codes.iter()) {
+            assert_eq!(dense.to_code(dense_code), *code);
+            assert_eq!(
+                DenseSource::from_code(code).to_dense(code).as_ref(),
+                Some(dense_code)
+            );
+        }
+        for ((a, dense_a), (b, dense_b)) in
+            zip(codes.iter(), dense_codes.iter()).tuple_combinations()
+        {
+            assert_eq!(
+                dense.competitive_advantage(dense_a, dense_b),
+                a.competitive_advantage(b).unwrap()
+            );
+            assert_eq!(dense.beats(dense_a, dense_b), a.beats(b).unwrap());
+            assert_eq!(dense.ties(dense_a, dense_b), a.ties(b).unwrap());
+        }
+
+        let mut other_source = codes[0].clone();
+        let code_word = other_source.keys().next().unwrap().clone();
+        other_source.remove(&code_word);
+        assert!(dense.to_dense(&other_source).is_none());
+        other_source.insert(CodeWord::new('z', 1), 1);
+        assert!(dense.to_dense(&other_source).is_none());
+    }
+}
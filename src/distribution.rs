@@ -0,0 +1,405 @@
+use crate::source::Source;
+
+use itertools::Itertools;
+use rand::distributions::WeightedIndex;
+use rand::seq::{index, SliceRandom};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+//Real valued weights are rounded to integers with the largest at this
+//scale, which keeps the total of a few thousand weights within a u32
+const SCALE: f64 = (1 << 20) as f64;
+
+//How random sources are drawn. Named on the command line and in JSON as
+//`name` or `name:parameter`, such as `zipf:1.5`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Distribution {
+    //Independent integers in 1..size², fewer for thousands of symbols so
+    //the total fits in a u32, what Source::new samples
+    #[default]
+    Uniform,
+    //A uniformly random point of the simplex, in steps of 1/size²
+    Simplex,
+    //Dirichlet(α) with every concentration α
+    Dirichlet(f64),
+    //The counts of 16·size² draws from a Zipf law with exponent s, plus one
+    Zipf(f64),
+    //The counts of 16·size² draws from a geometric law with success
+    //probability p truncated to size symbols, plus one
+    Geometric(f64),
+    //A random dyadic source with each weight scaled by up to 1 ± ε
+    DyadicPerturbed(f64),
+    //Each weight is one of k values drawn from 1..size²
+    ForcedTies(usize),
+    //Fibonacci-like weights, each the sum of the two before it from two
+    //random starting weights, whose Huffman trees are as deep as possible
+    Fibonacci,
+}
+
+impl Distribution {
+    pub fn all() -> Vec<Distribution> {
+        vec![
+            Distribution::Uniform,
+            Distribution::Simplex,
+            Distribution::Dirichlet(1.0),
+            Distribution::Zipf(1.0),
+            Distribution::Geometric(0.5),
+            Distribution::DyadicPerturbed(0.1),
+            Distribution::ForcedTies(3),
+            Distribution::Fibonacci,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Distribution::Uniform => "uniform",
+            Distribution::Simplex => "simplex",
+            Distribution::Dirichlet(_) => "dirichlet",
+            Distribution::Zipf(_) => "zipf",
+            Distribution::Geometric(_) => "geometric",
+            Distribution::DyadicPerturbed(_) => "dyadic",
+            Distribution::ForcedTies(_) => "ties",
+            Distribution::Fibonacci => "fibonacci",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Distribution::Uniform => "independent integer weights in 1..n²",
+            Distribution::Simplex => "uniform on the simplex",
+            Distribution::Dirichlet(_) => "Dirichlet with concentration α",
+            Distribution::Zipf(_) => "sampled counts of a Zipf law, exponent s",
+            Distribution::Geometric(_) => {
+                "sampled counts of a geometric law, success probability p"
+            }
+            Distribution::DyadicPerturbed(_) => {
+                "random dyadic weights scaled by up to 1 ± ε"
+            }
+            Distribution::ForcedTies(_) => {
+                "weights drawn from k distinct values"
+            }
+            Distribution::Fibonacci => "Fibonacci-like worst cases",
+        }
+    }
+
+    pub fn sample<R: Rng>(&self, size: usize, rng: &mut R) -> Source<u32> {
+        if size == 0 {
+            return Source::from_weights(vec![]);
+        }
+        let weights = match *self {
+            Distribution::Uniform => return Source::new(size, rng),
+            Distribution::Simplex => return Source::new_int_uniform(size, rng),
+            Distribution::Dirichlet(alpha) => scaled_weights(
+                &(0..size).map(|_| gamma(alpha, rng)).collect_vec(),
+            ),
+            Distribution::Zipf(s) => sampled_counts(
+                &(1..=size).map(|k| (k as f64).powf(-s)).collect_vec(),
+                rng,
+            ),
+            Distribution::Geometric(p) => sampled_counts(
+                &(0..size).map(|k| (1.0 - p).powi(k as i32)).collect_vec(),
+                rng,
+            ),
+            Distribution::DyadicPerturbed(epsilon) => scaled_weights(
+                &random_full_tree_depths(size, rng)
+                    .into_iter()
+                    .map(|depth| {
+                        let perturbation = rng.gen_range(-1.0..=1.0) * epsilon;
+                        0.5f64.powi(depth as i32) * (1.0 + perturbation)
+                    })
+                    .collect_vec(),
+            ),
+            Distribution::ForcedTies(k) => {
+                //At most one value per symbol, each below size² capped as
+                //for Uniform, drawn without listing the whole range
+                let k = k.min(size);
+                let max_weight =
+                    (size * size).min(u32::MAX as usize / size).max(k + 1);
+                let values = index::sample(rng, max_weight - 1, k)
+                    .into_iter()
+                    .map(|i| i as u32 + 1)
+                    .collect_vec();
+                (0..size)
+                    .map(|_| *values.choose(rng).unwrap())
+                    .collect_vec()
+            }
+            Distribution::Fibonacci => fibonacci_like(size, rng),
+        };
+        Source::from_weights(weights)
+    }
+
+    fn parameter(&self) -> Option<f64> {
+        match *self {
+            Distribution::Dirichlet(x)
+            | Distribution::Zipf(x)
+            | Distribution::Geometric(x)
+            | Distribution::DyadicPerturbed(x) => Some(x),
+            Distribution::ForcedTies(k) => Some(k as f64),
+            Distribution::Uniform
+            | Distribution::Simplex
+            | Distribution::Fibonacci => None,
+        }
+    }
+}
+
+impl fmt::Display for Distribution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.parameter() {
+            Some(parameter) => write!(f, "{}:{}", self.name(), parameter),
+            None => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+impl FromStr for Distribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, parameter) = match s.split_once(':') {
+            Some((name, parameter)) => (name, Some(parameter)),
+            None => (s, None),
+        };
+        let default = Distribution::all()
+            .into_iter()
+            .find(|distribution| distribution.name() == name)
+            .ok_or_else(|| format!("Unknown distribution: {}", name))?;
+        let Some(parameter) = parameter else {
+            return Ok(default);
+        };
+        let invalid =
+            || format!("Invalid parameter for {}: {}", name, parameter);
+        let x: f64 = parameter
+            .parse()
+            .ok()
+            .filter(|x: &f64| x.is_finite())
+            .ok_or_else(invalid)?;
+        let distribution = match default {
+            Distribution::Dirichlet(_) if x > 0.0 => Distribution::Dirichlet(x),
+            Distribution::Zipf(_) if x >= 0.0 => Distribution::Zipf(x),
+            Distribution::Geometric(_) if x > 0.0 && x < 1.0 => {
+                Distribution::Geometric(x)
+            }
+            Distribution::DyadicPerturbed(_) if (0.0..1.0).contains(&x) => {
+                Distribution::DyadicPerturbed(x)
+            }
+            Distribution::ForcedTies(_) => Distribution::ForcedTies(
+                parameter.parse().map_err(|_| invalid())?,
+            ),
+            _ => return Err(invalid()),
+        };
+        match distribution {
+            Distribution::ForcedTies(0) => Err(invalid()),
+            distribution => Ok(distribution),
+        }
+    }
+}
+
+impl From<Distribution> for String {
+    fn from(distribution: Distribution) -> String {
+        distribution.to_string()
+    }
+}
+
+impl TryFrom<String> for Distribution {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+//Rounds positive values to positive integers, the largest becoming SCALE
+fn scaled_weights(values: &[f64]) -> Vec<u32> {
+    let max = values.iter().copied().fold(f64::MIN_POSITIVE, f64::max);
+    values
+        .iter()
+        .map(|value| ((value / max * SCALE).round() as u32).max(1))
+        .collect_vec()
+}
+
+fn sampled_counts<R: Rng>(probabilities: &[f64], rng: &mut R) -> Vec<u32> {
+    let draws = 16 * probabilities.len() * probabilities.len();
+    let index = WeightedIndex::new(probabilities).unwrap();
+    let mut counts = vec![1; probabilities.len()];
+    for _ in 0..draws {
+        counts[rng.sample(&index)] += 1;
+    }
+    counts
+}
+
+//Standard normal by the Box-Muller transform
+fn normal<R: Rng>(rng: &mut R) -> f64 {
+    let u: f64 = 1.0 - rng.gen::<f64>();
+    let v: f64 = rng.gen();
+    (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos()
+}
+
+//Gamma(α, 1) by Marsaglia and Tsang's method, boosted from α + 1 for α < 1
+fn gamma<R: Rng>(alpha: f64, rng: &mut R) -> f64 {
+    if alpha < 1.0 {
+        let u: f64 = 1.0 - rng.gen::<f64>();
+        return gamma(alpha + 1.0, rng) * u.powf(1.0 / alpha);
+    }
+    let d = alpha - 1.0 / 3.0;
+    let c = 1.0 / (9.0 * d).sqrt();
+    loop {
+        let x = normal(rng);
+        let v = (1.0 + c * x).powi(3);
+        if v <= 0.0 {
+            continue;
+        }
+        let u: f64 = 1.0 - rng.gen::<f64>();
+        if u.ln() < 0.5 * x * x + d - d * v + d * v.ln() {
+            return d * v;
+        }
+    }
+}
+
+//Leaf depths of a full binary tree grown by splitting random leaves
+fn random_full_tree_depths<R: Rng>(size: usize, rng: &mut R) -> Vec<u32> {
+    let mut depths = if size == 0 { vec![] } else { vec![0] };
+    while depths.len() < size {
+        let i = rng.gen_range(0..depths.len());
+        depths[i] += 1;
+        depths.push(depths[i]);
+    }
+    depths
+}
+
+//Exact while the total fits in a u32, scaled beyond that
+fn fibonacci_like<R: Rng>(size: usize, rng: &mut R) -> Vec<u32> {
+    let max_start = size.max(2) as u64;
+    let mut weights = vec![rng.gen_range(1..=max_start)];
+    weights.push(rng.gen_range(weights[0]..=max_start));
+    while weights.len() < size {
+        let n = weights.len();
+        weights.push(weights[n - 1].saturating_add(weights[n - 2]));
+    }
+    weights.truncate(size);
+    let total = weights
+        .iter()
+        .fold(0u64, |total, &w| total.saturating_add(w));
+    if total <= u32::MAX as u64 {
+        weights.into_iter().map(|w| w as u32).collect_vec()
+    } else {
+        scaled_weights(&weights.into_iter().map(|w| w as f64).collect_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Node;
+    use crate::source::SourceSeed;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn weights(source: &Source<u32>) -> Vec<u32> {
+        source.iter().map(|&(_, w)| w).collect_vec()
+    }
+
+    #[test]
+    fn from_str_test() {
+        for distribution in Distribution::all() {
+            let name = distribution.to_string();
+            assert_eq!(name.parse(), Ok(distribution));
+            assert_eq!(distribution.name().parse(), Ok(distribution));
+        }
+        assert_eq!("zipf:1.5".parse(), Ok(Distribution::Zipf(1.5)));
+        assert_eq!("ties:4".parse(), Ok(Distribution::ForcedTies(4)));
+        assert_eq!(Distribution::Dirichlet(0.5).to_string(), "dirichlet:0.5");
+        for invalid in [
+            "normal",
+            "zipf:x",
+            "dirichlet:0",
+            "dirichlet:inf",
+            "zipf:inf",
+            "zipf:NaN",
+            "dyadic:-inf",
+            "geometric:1",
+            "dyadic:1",
+            "ties:0",
+            "ties:1.5",
+            "fibonacci:2",
+        ] {
+            assert!(invalid.parse::<Distribution>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn sample_test() {
+        let mut distributions = Distribution::all();
+        distributions.push(Distribution::Dirichlet(0.1));
+        distributions.push(Distribution::Zipf(2.5));
+        for distribution in distributions {
+            for size in [1, 2, 8, 30] {
+                let seed = SourceSeed::new(25, size as u64)
+                    .with_distribution(distribution);
+                let source = Source::replay(size, seed);
+                let source_weights = weights(&source);
+                assert_eq!(source_weights.len(), size);
+                assert!(source_weights.iter().all(|&w| w > 0));
+                let total: u64 = source_weights.iter().map(|&w| w as u64).sum();
+                assert!(total <= u32::MAX as u64);
+                assert_eq!(
+                    weights(&Source::replay(size, seed)),
+                    source_weights
+                );
+            }
+        }
+
+        for distribution in Distribution::all() {
+            let seed = SourceSeed::new(25, 0).with_distribution(distribution);
+            assert!(weights(&Source::replay(0, seed)).is_empty());
+        }
+        let mut rng = StdRng::seed_from_u64(25);
+        assert!(random_full_tree_depths(0, &mut rng).is_empty());
+        assert_eq!(random_full_tree_depths(1, &mut rng), vec![0]);
+
+        //Seeds without a distribution replay the old sampler's sources
+        let seed = SourceSeed::new(1, 2);
+        assert_eq!(
+            weights(&Source::replay(8, seed)),
+            weights(&Source::new(8, &mut seed.rng()))
+        );
+    }
+
+    #[test]
+    fn shapes_test() {
+        let mut rng = StdRng::seed_from_u64(25);
+        for _ in 0..20 {
+            let source = Distribution::ForcedTies(2).sample(10, &mut rng);
+            assert!(weights(&source).into_iter().unique().count() <= 2);
+            let source =
+                Distribution::ForcedTies(usize::MAX).sample(10, &mut rng);
+            assert_eq!(weights(&source).len(), 10);
+
+            //Unperturbed dyadic weights are powers of two adding up to one
+            let source =
+                Distribution::DyadicPerturbed(0.0).sample(10, &mut rng);
+            let source_weights = weights(&source);
+            assert!(source_weights.iter().all(|w| w.is_power_of_two()));
+            let total: u32 = source_weights.iter().sum();
+            assert!(total.is_power_of_two());
+
+            let source = Distribution::Fibonacci.sample(12, &mut rng);
+            let tree = Node::new_huffman(source.to_leaves_vec()).unwrap();
+            assert_eq!(tree.height(), 11);
+
+            //Zipf counts fall off with rank
+            let source = Distribution::Zipf(2.0).sample(6, &mut rng);
+            let source_weights = weights(&source);
+            assert!(source_weights[0] > source_weights[5]);
+        }
+        let source = Distribution::ForcedTies(3000).sample(3000, &mut rng);
+        let total: u64 = weights(&source).iter().map(|&w| w as u64).sum();
+        assert!(total <= u32::MAX as u64);
+        let source = Distribution::Fibonacci.sample(100, &mut rng);
+        let total: u64 = weights(&source).iter().map(|&w| w as u64).sum();
+        assert!(total <= u32::MAX as u64);
+    }
+}
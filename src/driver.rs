@@ -0,0 +1,177 @@
+use crate::conjectures::{Conjecture, Outcome, Sample};
+use crate::report::Counterexample;
+use crate::source::{ExhaustiveSources, SourceGenerator};
+
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug)]
+pub struct RunSummary {
+    pub sources_tested: u64,
+    pub sources_skipped: u64,
+    pub counterexample: Option<Counterexample>,
+    //Whether every source in scope was checked, so that finding no
+    //counterexample proves the conjecture for that scope
+    pub exhaustive: bool,
+    //Whether sampling stopped early because the conjecture applied to none
+    //of the first SKIP_LIMIT sources
+    pub gave_up: bool,
+}
+
+//Sampling gives up once this many sources have been skipped without any
+//being tested, such as when every source of the size has Huffman codes
+//that all tie
+const SKIP_LIMIT: u64 = 10_000;
+
+#[derive(Default)]
+struct Tally {
+    sources_tested: AtomicU64,
+    sources_skipped: AtomicU64,
+}
+
+impl Tally {
+    fn record(
+        &self,
+        conjecture: &dyn Conjecture,
+        sample: Sample,
+    ) -> Option<Counterexample> {
+        match evaluate(conjecture, &sample) {
+            Outcome::Pass => {
+                self.sources_tested.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            Outcome::Skip => {
+                self.sources_skipped.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            Outcome::Counterexample(witness) => {
+                Some(Counterexample::new(conjecture.name(), &sample, *witness))
+            }
+        }
+    }
+
+    fn gave_up(&self) -> bool {
+        self.sources_tested.load(Ordering::Relaxed) == 0
+            && self.sources_skipped.load(Ordering::Relaxed) >= SKIP_LIMIT
+    }
+
+    fn into_summary(
+        self,
+        counterexample: Option<Counterexample>,
+        exhaustive: bool,
+    ) -> RunSummary {
+        RunSummary {
+            gave_up: !exhaustive && counterexample.is_none() && self.gave_up(),
+            sources_tested: self.sources_tested.into_inner(),
+            sources_skipped: self.sources_skipped.into_inner(),
+            counterexample,
+            exhaustive,
+        }
+    }
+}
+
+pub fn evaluate(conjecture: &dyn Conjecture, sample: &Sample) -> Outcome {
+    if conjecture.applies_to(sample) {
+        conjecture.check(sample)
+    } else {
+        Outcome::Skip
+    }
+}
+
+//Samples sources on every thread of the current rayon pool until
+//`sources_to_test` of them have been checked, one thread finds a
+//counterexample or the conjecture applies to none of the first SKIP_LIMIT.
+//Skipped sources don't count towards `sources_to_test`.
+pub fn run(
+    conjecture: &dyn Conjecture,
+    source_size: usize,
+    sources_to_test: u64,
+    generator: &SourceGenerator,
+) -> RunSummary {
+    let tally = Tally::default();
+    let counterexample = (0..rayon::current_num_threads())
+        .into_par_iter()
+        .find_map_any(|_| {
+            while tally.sources_tested.load(Ordering::Relaxed) < sources_to_test
+                && !tally.gave_up()
+            {
+                let (source_seed, source) = generator.sample(source_size);
+                let sample = Sample::new(Some(source_seed), source);
+                let counterexample = tally.record(conjecture, sample);
+                if counterexample.is_some() {
+                    return counterexample;
+                }
+            }
+            None
+        });
+    tally.into_summary(counterexample, false)
+}
+
+//Checks every source of `source_size` integer weights summing to at most
+//`max_total`. Conjectures only depend on the multiset of weights, so if
+//no counterexample turns up the conjecture holds for all such sources.
+pub fn run_exhaustive(
+    conjecture: &dyn Conjecture,
+    source_size: usize,
+    max_total: u32,
+) -> RunSummary {
+    let tally = Tally::default();
+    let counterexample = ExhaustiveSources::new(source_size, max_total)
+        .par_bridge()
+        .find_map_any(|source| {
+            tally.record(conjecture, Sample::new(None, source))
+        });
+    tally.into_summary(counterexample, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conjectures::{conjectures, MIN_APPLICABLE_SIZE};
+
+    #[test]
+    fn run_test() {
+        for conjecture in conjectures() {
+            let generator = SourceGenerator::new(5);
+            let summary = run(conjecture.as_ref(), 5, 20, &generator);
+            assert!(summary.counterexample.is_none());
+            assert!(summary.sources_tested >= 20);
+            assert!(summary.sources_skipped > 0);
+            assert!(!summary.exhaustive);
+            assert!(!summary.gave_up);
+
+            //Huffman codes of three symbols all tie
+            let summary = run(conjecture.as_ref(), 3, 20, &generator);
+            assert!(summary.gave_up);
+            assert_eq!(summary.sources_tested, 0);
+            assert!(summary.sources_skipped >= SKIP_LIMIT);
+        }
+    }
+
+    #[test]
+    fn min_applicable_size_test() {
+        for conjecture in conjectures() {
+            for size in 2..MIN_APPLICABLE_SIZE {
+                let summary = run_exhaustive(conjecture.as_ref(), size, 30);
+                assert_eq!(summary.sources_tested, 0);
+            }
+            let summary =
+                run_exhaustive(conjecture.as_ref(), MIN_APPLICABLE_SIZE, 30);
+            assert!(summary.sources_tested > 0);
+        }
+    }
+
+    #[test]
+    fn run_exhaustive_test() {
+        for conjecture in conjectures() {
+            let summary = run_exhaustive(conjecture.as_ref(), 5, 12);
+            assert!(summary.counterexample.is_none());
+            assert!(summary.exhaustive);
+            assert!(!summary.gave_up);
+            assert_eq!(
+                summary.sources_tested + summary.sources_skipped,
+                ExhaustiveSources::new(5, 12).count() as u64
+            );
+        }
+    }
+}
@@ -0,0 +1,353 @@
+//On-disk formats for sources, trees and codes.
+//
+//JSON is serde's encoding of each type. A source is a list of
+//[symbol, weight] pairs, where a symbol is a number for an index or a string
+//for a label, and a file of sources is a list of sources. A code is a list
+//of [{"source_symbol", "probability"}, depth] entries sorted by symbol, and a
+//tree nests {"probability", "node_type"} objects.
+//
+//The text format has one "symbol weight" line per symbol of a source, or
+//"symbol weight depth" per code word of a code. Symbols that parse as an
+//integer are indices and any other word is a label, so labels can't be
+//integers, contain whitespace or start with '#'. Weights are anything
+//positive the weight type parses, such as 3, or 1/3 for exact
+//probabilities. Blank lines separate the sources of a file of sources and
+//lines starting with '#' are comments.
+
+use crate::code::{Code, CodeWord, Depth, New};
+use crate::node::RealNum;
+use crate::source::{Source, Symbol};
+
+use itertools::Itertools;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Write};
+use std::fs::File;
+use std::hash::Hash;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+use std::str::FromStr;
+
+pub fn save_json<V: Serialize>(value: &V, path: &Path) -> io::Result<()> {
+    let writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(writer, value)?;
+    Ok(())
+}
+
+pub fn load_json<V: DeserializeOwned>(path: &Path) -> io::Result<V> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(reader)?)
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "T: Serialize",
+    deserialize = "T: Deserialize<'de> + Eq + Hash"
+))]
+struct CodeEntries<T>(#[serde(with = "crate::code::code_entries")] Code<T>);
+
+#[allow(dead_code)]
+pub fn save_code_json<T>(code: &Code<T>, path: &Path) -> io::Result<()>
+where
+    T: RealNum + Serialize,
+{
+    save_json(&CodeEntries(code.clone()), path)
+}
+
+#[allow(dead_code)]
+pub fn load_code_json<T>(path: &Path) -> io::Result<Code<T>>
+where
+    T: RealNum + DeserializeOwned,
+{
+    Ok(load_json::<CodeEntries<T>>(path)?.0)
+}
+
+fn symbol_to_text(symbol: &Symbol) -> Result<String, String> {
+    match symbol {
+        Symbol::Index(index) => Ok(index.to_string()),
+        Symbol::Label(label)
+            if label.is_empty()
+                || label.starts_with('#')
+                || label.contains(char::is_whitespace)
+                || label.parse::<usize>().is_ok() =>
+        {
+            Err(format!("Label {:?} can't be written as text", label))
+        }
+        Symbol::Label(label) => Ok(label.to_string()),
+    }
+}
+
+fn symbol_from_text(token: &str) -> Symbol {
+    token
+        .parse()
+        .map(Symbol::Index)
+        .unwrap_or_else(|_| Symbol::from(token))
+}
+
+fn parse_field<F: FromStr>(
+    token: Option<&str>,
+    line: &str,
+) -> Result<F, String> {
+    token
+        .and_then(|token| token.parse().ok())
+        .ok_or_else(|| format!("Can't parse line: {}", line))
+}
+
+//The lines of every block of text, where blocks are separated by blank
+//lines, without comments
+fn blocks(text: &str) -> Vec<Vec<&str>> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .group_by(|line| line.is_empty())
+        .into_iter()
+        .filter(|(empty, _)| !empty)
+        .map(|(_, lines)| lines.collect_vec())
+        .collect_vec()
+}
+
+pub fn sources_to_text<T>(sources: &[Source<T>]) -> Result<String, String>
+where
+    T: RealNum + Display,
+{
+    let mut text = String::new();
+    for (i, source) in sources.iter().enumerate() {
+        if i > 0 {
+            text.push('\n');
+        }
+        for (symbol, weight) in source.iter() {
+            writeln!(text, "{} {}", symbol_to_text(symbol)?, weight).unwrap();
+        }
+    }
+    Ok(text)
+}
+
+//Err for an empty source, a repeated symbol, which codes would merge into
+//a single code word, or a weight that isn't positive, which
+//`Source::from_rationals` rejects too
+fn check_sources<T>(sources: &[Source<T>]) -> Result<(), String>
+where
+    T: RealNum + Display,
+{
+    for source in sources {
+        if source.iter().next().is_none() {
+            return Err("Source has no symbols".to_string());
+        }
+        if let Some(symbol) =
+            source.iter().map(|(symbol, _)| symbol).duplicates().next()
+        {
+            return Err(format!("Symbol {} appears more than once", symbol));
+        }
+        if let Some((_, weight)) =
+            source.iter().find(|(_, weight)| *weight <= T::zero())
+        {
+            return Err(format!("Probability {} is not positive", weight));
+        }
+    }
+    Ok(())
+}
+
+pub fn sources_from_text<T>(text: &str) -> Result<Vec<Source<T>>, String>
+where
+    T: RealNum + Display + FromStr,
+{
+    let sources = blocks(text)
+        .into_iter()
+        .map(|lines| {
+            let symbols = lines
+                .into_iter()
+                .map(|line| {
+                    let mut tokens = line.split_whitespace();
+                    let symbol = symbol_from_text(tokens.next().unwrap());
+                    let weight = parse_field(tokens.next(), line)?;
+                    match tokens.next() {
+                        Some(_) => Err(format!("Can't parse line: {}", line)),
+                        None => Ok((symbol, weight)),
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Source::from_vec(symbols))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    check_sources(&sources)?;
+    Ok(sources)
+}
+
+#[allow(dead_code)]
+pub fn code_to_text<T>(code: &Code<T>) -> Result<String, String>
+where
+    T: RealNum + Display,
+{
+    let mut text = String::new();
+    for (code_word, depth) in code
+        .iter()
+        .sorted_by_key(|(code_word, _)| code_word.source_symbol())
+    {
+        writeln!(
+            text,
+            "{} {} {}",
+            symbol_to_text(code_word.source_symbol())?,
+            code_word.probability(),
+            depth
+        )
+        .unwrap();
+    }
+    Ok(text)
+}
+
+#[allow(dead_code)]
+pub fn code_from_text<T>(text: &str) -> Result<Code<T>, String>
+where
+    T: RealNum + FromStr,
+{
+    let mut code = Code::new();
+    for line in blocks(text).into_iter().flatten() {
+        let mut tokens = line.split_whitespace();
+        let symbol = symbol_from_text(tokens.next().unwrap());
+        let weight: T = parse_field(tokens.next(), line)?;
+        let depth: Depth = parse_field(tokens.next(), line)?;
+        if tokens.next().is_some() {
+            return Err(format!("Can't parse line: {}", line));
+        }
+        code.insert(CodeWord::new(symbol, weight), depth);
+    }
+    Ok(code)
+}
+
+pub fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "json")
+}
+
+fn invalid_data(error: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+//JSON for files ending in .json, text otherwise
+#[allow(dead_code)]
+pub fn save_sources<T>(sources: &[Source<T>], path: &Path) -> io::Result<()>
+where
+    T: RealNum + Display + Serialize,
+{
+    if is_json(path) {
+        save_json(&sources, path)
+    } else {
+        std::fs::write(path, sources_to_text(sources).map_err(invalid_data)?)
+    }
+}
+
+pub fn load_sources<T>(path: &Path) -> io::Result<Vec<Source<T>>>
+where
+    T: RealNum + Display + FromStr + DeserializeOwned,
+{
+    if is_json(path) {
+        let sources: Vec<Source<T>> = load_json(path)?;
+        check_sources(&sources).map_err(invalid_data)?;
+        Ok(sources)
+    } else {
+        sources_from_text(&std::fs::read_to_string(path)?).map_err(invalid_data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::FromNode;
+    use crate::node::Node;
+    use num::BigRational;
+
+    fn sources() -> Vec<Source<u32>> {
+        vec![
+            Source::from_weights(vec![3, 1, 2]),
+            Source::from_vec(vec![("x", 5), ("y", 5)]),
+        ]
+    }
+
+    fn weights(source: &Source<u32>) -> Vec<(Symbol, u32)> {
+        source.iter().cloned().collect_vec()
+    }
+
+    #[test]
+    fn text_test() {
+        let text = sources_to_text(&sources()).unwrap();
+        assert_eq!(text, "0 3\n1 1\n2 2\n\nx 5\ny 5\n");
+        let parsed = sources_from_text::<u32>(
+            "# near miss\n0 3\n1   1\n2 2\n\n\n# tie\nx 5\n  y 5  \n",
+        )
+        .unwrap();
+        assert_eq!(parsed.iter().map(weights).collect_vec(), {
+            sources().iter().map(weights).collect_vec()
+        });
+
+        let exact = sources_from_text::<BigRational>("a 1/3\nb 2/3\n").unwrap();
+        let integers = exact[0].to_u32_weights().unwrap();
+        assert_eq!(
+            weights(&integers),
+            vec![(Symbol::from("a"), 1), (Symbol::from("b"), 2)]
+        );
+
+        assert!(sources_from_text::<u32>("a\n").is_err());
+        assert!(sources_from_text::<u32>("a 1 2\n").is_err());
+        assert!(sources_from_text::<u32>("a -1\n").is_err());
+        assert!(sources_from_text::<u32>("a 1\nb 0\n").is_err());
+        assert!(sources_from_text::<u32>("a 1\na 1\nb 1\n").is_err());
+        assert!(sources_from_text::<u32>("a 1\n\nb 1\n0 2\n0 3\n").is_err());
+        assert!(sources_from_text::<BigRational>("a 1/2\nb -1/2\n").is_err());
+        let unwritable = Source::from_vec(vec![("12", 1)]);
+        assert!(sources_to_text(&[unwritable]).is_err());
+        let unwritable = Source::from_vec(vec![("a b", 1)]);
+        assert!(sources_to_text(&[unwritable]).is_err());
+    }
+
+    #[test]
+    fn code_text_test() {
+        let source = Source::from_vec(vec![('a', 1), ('b', 1), ('c', 2)]);
+        let tree = Node::new_huffman(source.to_leaves_vec()).unwrap();
+        let code = Code::from_node(&tree);
+        let text = code_to_text(&code).unwrap();
+        assert_eq!(text.lines().count(), 3);
+        assert!(text.contains("c 2 1\n"));
+        assert_eq!(code_from_text::<u32>(&text).unwrap(), code);
+        assert!(code_from_text::<u32>("a 1\n").is_err());
+    }
+
+    #[test]
+    fn save_load_test() {
+        let directory = std::env::temp_dir();
+        for name in ["files_test_sources.json", "files_test_sources.txt"] {
+            let path = directory.join(name);
+            save_sources(&sources(), &path).unwrap();
+            let loaded = load_sources::<u32>(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.iter().map(weights).collect_vec(), {
+                sources().iter().map(weights).collect_vec()
+            });
+        }
+
+        let source = Source::from_vec(vec![('a', 1), ('b', 1), ('c', 2)]);
+        let tree = Node::new_huffman(source.to_leaves_vec()).unwrap();
+        let code = Code::from_node(&tree);
+        let path = directory.join("files_test_tree.json");
+        save_json(&tree, &path).unwrap();
+        let loaded: Node<u32> = load_json(&path).unwrap();
+        assert!(loaded.is_same_as(&tree));
+        save_code_json(&code, &path).unwrap();
+        let loaded: Code<u32> = load_code_json(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, code);
+
+        let path = directory.join("files_test_invalid.json");
+        for invalid in [
+            r#"[[[0, 1], [1, 0]]]"#,
+            r#"[[["a", 1], ["a", 1], ["b", 1]]]"#,
+            r#"[[[0, 1]], []]"#,
+        ] {
+            std::fs::write(&path, invalid).unwrap();
+            assert!(load_sources::<u32>(&path).is_err(), "{}", invalid);
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        let path = directory.join("files_test_missing.txt");
+        assert!(load_sources::<u32>(&path).is_err());
+    }
+}
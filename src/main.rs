@@ -1,22 +1,598 @@
+mod bits;
 mod code;
+mod coder;
 mod conjectures;
+mod dary;
+mod dense;
+mod distribution;
+mod driver;
+mod files;
 mod node;
+mod optimality;
+mod reduction;
+mod render;
+mod report;
 mod source;
 
-use crate::conjectures::no_huffman_dominates_another_and_is_optimal;
-use crate::node::Node;
+use crate::bits::{canonical_codebook, codebook_from_node, format_codebook};
+use crate::code::{Code, CompetitiveOrd, FromNode};
+use crate::coder::{Decoder, Encoder};
+use crate::conjectures::{
+    conjectures, find_conjecture, Conjecture, Outcome, Sample,
+    MIN_APPLICABLE_SIZE,
+};
+use crate::dary::{dary_leaves, DaryNode};
+use crate::distribution::Distribution;
+use crate::driver::evaluate;
+use crate::files::{is_json, load_sources, sources_from_text};
+use crate::node::{Node, RealNum};
+use crate::optimality::{beating_code, beating_dary_code};
+use crate::reduction::distinct_huffman_codes;
+use crate::render::{to_ascii, to_dot};
+use crate::report::Counterexample;
+use crate::source::{Source, SourceGenerator, SourceSeed, Symbol};
 
-use rayon::prelude::*;
+use clap::{Parser, Subcommand, ValueEnum};
+use itertools::Itertools;
+use num::{BigRational, BigUint};
+use rand::{thread_rng, Rng};
+use rayon::ThreadPoolBuilder;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-fn main() {
-    for source_size in 7..9 {
+#[derive(Parser)]
+#[command(about = "Search for counterexamples to competitive optimality \
+                   conjectures about Huffman codes")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a conjecture on randomly generated sources, or on every source
+    /// up to a total weight
+    Run {
+        /// Name of the conjecture, see `list`
+        conjecture: String,
+        /// Smallest source size to test, at least 5 as no conjecture
+        /// applies to smaller sources
+        #[arg(long, default_value_t = 7)]
+        min_size: usize,
+        /// Largest source size to test (inclusive)
+        #[arg(long, default_value_t = 8)]
+        max_size: usize,
+        /// Number of sources to test per source size, not counting sources
+        /// the conjecture doesn't apply to
+        #[arg(long, default_value_t = 9_600_000)]
+        sources: u64,
+        /// Instead of sampling, check every source of integer weights that
+        /// sum to at most this total
+        #[arg(long, value_name = "MAX_TOTAL")]
+        exhaustive: Option<u32>,
+        /// Number of parallel workers
+        #[arg(long, default_value_t = 8)]
+        workers: usize,
+        /// Seed for the random number generator, random if not given
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Distribution sources are sampled from, as `name` or
+        /// `name:parameter`, see `list`
+        #[arg(long, default_value = "uniform")]
+        distribution: Distribution,
+        /// File the counterexample is written to as JSON, if one is found
+        #[arg(long, default_value = "counterexample.json")]
+        output: PathBuf,
+    },
+    /// Print a counterexample previously written by `run`
+    Show {
+        path: PathBuf,
+        /// Print the witness tree as Graphviz DOT, with the leaves flagged
+        /// by the sibling difference check highlighted
+        #[arg(long)]
+        dot: bool,
+    },
+    /// Regenerate a single source from the seed and index it was sampled
+    /// with
+    Replay {
+        #[arg(long)]
+        seed: u64,
+        #[arg(long)]
+        index: u64,
+        /// Size of the source
+        #[arg(long)]
+        size: usize,
+        /// Distribution the source was sampled from
+        #[arg(long, default_value = "uniform")]
+        distribution: Distribution,
+        /// Also check the source against this conjecture
+        #[arg(long)]
+        conjecture: Option<String>,
+    },
+    /// Check one source, given as exact probabilities such as
+    /// "1/3, 1/3, 1/6, 1/6", against every conjecture
+    Check {
+        #[arg(required_unless_present = "file")]
+        probabilities: Option<String>,
+        /// Check the empirical frequencies of a file's symbols instead
+        #[arg(long, conflicts_with = "probabilities")]
+        file: Option<PathBuf>,
+        /// What counts as a symbol of the file
+        #[arg(long, value_enum, default_value_t = Unit::Byte)]
+        unit: Unit,
+        /// Size of the code alphabet; the conjectures are only checked for
+        /// binary codes
+        #[arg(long, default_value_t = 2)]
+        arity: usize,
+        /// Only list the Huffman trees and count the Huffman codes of
+        /// sources with at most this many Huffman trees, as the codes can
+        /// be too many to count; otherwise one tree is listed
+        #[arg(long, default_value_t = 1000)]
+        max_trees: u64,
+    },
+    /// Huffman code the bytes of a file with their empirical frequencies,
+    /// and compare with a code that competitively beats Huffman's, if any
+    Compress { path: PathBuf },
+    /// Run every conjecture on each source in a file, read as JSON if its
+    /// name ends in .json and as text otherwise (see src/files.rs)
+    Batch { path: PathBuf },
+    /// List the available conjectures and source distributions
+    List,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Unit {
+    Byte,
+    Char,
+    /// Whitespace separated tokens
+    Token,
+}
+
+fn read_source(path: &Path, unit: Unit) -> Result<Source<u32>, String> {
+    let bytes = std::fs::read(path).map_err(|error| {
+        format!("Could not read {}: {}", path.display(), error)
+    })?;
+    let source = match unit {
+        Unit::Byte => Source::from_bytes(&bytes),
+        Unit::Char | Unit::Token => {
+            let text = String::from_utf8(bytes).map_err(|error| {
+                format!("{} is not UTF-8: {}", path.display(), error)
+            })?;
+            match unit {
+                Unit::Char => Source::from_chars(&text),
+                _ => Source::from_tokens(&text),
+            }
+        }
+    };
+    source.ok_or_else(|| format!("{} has too many symbols", path.display()))
+}
+
+//Text files may give exact probabilities, which are scaled to integer
+//weights
+fn read_sources(path: &Path) -> Result<Vec<Source<u32>>, String> {
+    let error = |error| format!("Could not read {}: {}", path.display(), error);
+    if is_json(path) {
+        return load_sources(path).map_err(error);
+    }
+    let text = std::fs::read_to_string(path).map_err(error)?;
+    sources_from_text::<BigRational>(&text)?
+        .iter()
+        .map(|source| {
+            source.to_u32_weights().ok_or_else(|| {
+                format!("Weights of {:?} don't fit in u32", source)
+            })
+        })
+        .collect()
+}
+
+fn batch(sources: Vec<Source<u32>>) {
+    let mut counterexamples = 0;
+    for (i, source) in sources.into_iter().enumerate() {
+        println!("Source {}: {:?}", i, source);
+        let sample = Sample::new(None, source);
+        for conjecture in conjectures() {
+            match evaluate(conjecture.as_ref(), &sample) {
+                Outcome::Counterexample(witness) => {
+                    counterexamples += 1;
+                    println!("    {}: counterexample", conjecture.name());
+                    show(&Counterexample::new(
+                        conjecture.name(),
+                        &sample,
+                        *witness,
+                    ));
+                }
+                outcome => println!("    {}: {:?}", conjecture.name(), outcome),
+            }
+        }
+    }
+    println!("Counterexamples: {}", counterexamples);
+}
+
+fn check_run_arguments(
+    min_size: usize,
+    max_size: usize,
+    workers: usize,
+) -> Result<(), String> {
+    if min_size < MIN_APPLICABLE_SIZE || min_size > max_size || workers == 0 {
+        return Err(format!(
+            "Need {} <= min-size <= max-size and workers > 0, as every \
+             Huffman code of a smaller source ties with the others",
+            MIN_APPLICABLE_SIZE
+        ));
+    }
+    Ok(())
+}
+
+fn run(
+    conjecture: &dyn Conjecture,
+    source_sizes: std::ops::RangeInclusive<usize>,
+    sources: u64,
+    exhaustive: Option<u32>,
+    generator: &SourceGenerator,
+) -> Option<Counterexample> {
+    for source_size in source_sizes {
         println!("Source size: {}", source_size);
-        let conjecture_test_fn = no_huffman_dominates_another_and_is_optimal;
-        let counterexample_exists = vec![0; 8]
-            .par_iter()
-            .any(|_| !conjecture_test_fn(source_size, 1200000));
-        if counterexample_exists {
-            return;
+        let summary = match exhaustive {
+            Some(max_total) => {
+                driver::run_exhaustive(conjecture, source_size, max_total)
+            }
+            None => driver::run(conjecture, source_size, sources, generator),
+        };
+        println!(
+            "Sources tested: {}, skipped: {}",
+            summary.sources_tested, summary.sources_skipped
+        );
+        if summary.gave_up {
+            println!(
+                "Gave up: the conjecture applies to none of the {} sources \
+                 sampled",
+                summary.sources_skipped
+            );
+        }
+        if let (true, None, Some(max_total)) =
+            (summary.exhaustive, &summary.counterexample, exhaustive)
+        {
+            println!(
+                "Proved by exhaustion for every source of size {} with \
+                 total weight at most {}",
+                source_size, max_total
+            );
+        }
+        if summary.counterexample.is_some() {
+            return summary.counterexample;
+        }
+    }
+    None
+}
+
+fn format_code<T: RealNum + Display>(code: &Code<T>) -> String {
+    code.iter()
+        .sorted_by_key(|(code_word, _)| code_word.source_symbol())
+        .map(|(code_word, depth)| {
+            format!(
+                "{}({}):{}",
+                code_word.source_symbol(),
+                code_word.probability(),
+                depth
+            )
+        })
+        .join(" ")
+}
+
+fn show(counterexample: &Counterexample) {
+    println!("Conjecture: {}", counterexample.conjecture);
+    if let Some(source_seed) = counterexample.source_seed {
+        println!(
+            "Seed {}, index {}, distribution {}",
+            source_seed.seed, source_seed.index, source_seed.distribution
+        );
+    }
+    println!("Source: {:?}", counterexample.source);
+    println!("Witness tree:\n{}", to_ascii(&counterexample.witness_tree));
+    println!(
+        "Witness code: {}",
+        format_code(&counterexample.witness_code)
+    );
+    for comparison in counterexample.comparisons.iter() {
+        println!(
+            "    against {}: gained {}, lost {}",
+            format_code(&comparison.code),
+            comparison.competitive_advantage.gained,
+            comparison.competitive_advantage.lost
+        );
+    }
+    for tree in counterexample.huffman_trees.iter() {
+        println!("Huffman tree:\n{}", to_ascii(tree));
+    }
+}
+
+fn check(source: Source<BigRational>, max_trees: u64) {
+    let trees = source.count_huffman_trees();
+    let listed = trees <= BigUint::from(max_trees);
+    let huffman_trees = if listed {
+        let (codes, length_profiles) = source.count_huffman_codes();
+        println!(
+            "Huffman trees: {}, codes: {}, length profiles: {}",
+            trees, codes, length_profiles
+        );
+        distinct_huffman_codes(source.to_leaves_vec())
+    } else {
+        println!("Huffman trees: {}, too many to list, checking one", trees);
+        Node::new_huffman(source.to_leaves_vec())
+            .into_iter()
+            .collect()
+    };
+    for tree in huffman_trees {
+        let code = Code::from_node(&tree);
+        println!("Huffman code {}", format_code(&code));
+        println!(
+            "    code words {}",
+            format_codebook(&codebook_from_node(&tree))
+        );
+        match beating_code(&code) {
+            Some(better) => {
+                println!("    beaten by {}", format_code(&better));
+                let codebook = canonical_codebook(&better).unwrap();
+                println!("    code words {}", format_codebook(&codebook));
+            }
+            None => println!("    competitively optimal"),
+        }
+    }
+    let Some(weights) = source.to_u32_weights() else {
+        println!("Integer weights don't fit in u32, skipping conjectures");
+        return;
+    };
+    let sample = Sample::new(None, weights);
+    for conjecture in conjectures() {
+        println!(
+            "{}: {:?}",
+            conjecture.name(),
+            evaluate(conjecture.as_ref(), &sample)
+        );
+    }
+}
+
+fn check_dary(source: Source<BigRational>, arity: usize) {
+    let Some(tree) = DaryNode::new_huffman(dary_leaves(source), arity) else {
+        println!("No {}-ary Huffman code for an empty source", arity);
+        return;
+    };
+    let code = tree.code();
+    println!("{}-ary Huffman code {}", arity, format_code(&code));
+    match beating_dary_code(&code, arity as u64) {
+        Some(better) => println!("    beaten by {}", format_code(&better)),
+        None => println!("    competitively optimal"),
+    }
+}
+
+fn compress(bytes: &[u8]) {
+    let Some(source) = Source::from_bytes(bytes) else {
+        println!("Too many bytes to count in u32 weights");
+        return;
+    };
+    println!("Bytes: {}, distinct: {}", bytes.len(), source.len());
+    let Some(tree) = Node::new_huffman(source.to_leaves_vec()) else {
+        return;
+    };
+    let symbols = || bytes.iter().map(|&byte| byte as usize);
+    let codebook = codebook_from_node(&tree);
+    let encoded = Encoder::new(&codebook).encode(symbols()).unwrap();
+    let decoded = Decoder::new(&codebook).unwrap().decode(&encoded).unwrap();
+    assert!(decoded.into_iter().eq(symbols().map(Symbol::from)));
+    println!(
+        "Huffman code: {} bits ({} bytes)",
+        encoded.bits(),
+        encoded.bytes().len()
+    );
+    let code = Code::from_node(&tree);
+    if let Some(better) = beating_code(&code) {
+        let encoded = Encoder::canonical(&better)
+            .unwrap()
+            .encode(symbols())
+            .unwrap();
+        let advantage = better.competitive_advantage(&code).unwrap();
+        println!(
+            "Beaten by a code that is shorter on {} bytes and longer on {}: \
+             {} bits",
+            advantage.gained,
+            advantage.lost,
+            encoded.bits()
+        );
+    }
+}
+
+fn main() -> ExitCode {
+    match Cli::parse().command {
+        Command::List => {
+            for conjecture in conjectures() {
+                println!(
+                    "{}\n    {}",
+                    conjecture.name(),
+                    conjecture.description()
+                );
+            }
+            println!("\nDistributions:");
+            for distribution in Distribution::all() {
+                println!(
+                    "{}\n    {}",
+                    distribution,
+                    distribution.description()
+                );
+            }
+            ExitCode::SUCCESS
+        }
+        Command::Replay {
+            seed,
+            index,
+            size,
+            distribution,
+            conjecture,
+        } => {
+            let source_seed =
+                SourceSeed::new(seed, index).with_distribution(distribution);
+            let source = Source::replay(size, source_seed);
+            println!("{:?}", source);
+            if let Some(name) = conjecture {
+                let Some(conjecture) = find_conjecture(&name) else {
+                    eprintln!("Unknown conjecture: {}", name);
+                    return ExitCode::FAILURE;
+                };
+                let sample = Sample::new(Some(source_seed), source);
+                println!("{:#?}", evaluate(conjecture.as_ref(), &sample));
+            }
+            ExitCode::SUCCESS
+        }
+        Command::Check {
+            probabilities,
+            file,
+            unit,
+            arity,
+            max_trees,
+        } => {
+            let source = match (probabilities, file) {
+                (Some(probabilities), _) => probabilities.parse(),
+                (None, Some(path)) => {
+                    read_source(&path, unit).map(|source| source.to_rationals())
+                }
+                (None, None) => Err("Need probabilities or a file".to_string()),
+            };
+            match source {
+                Ok(_) if arity < 2 => {
+                    eprintln!("Need arity >= 2");
+                    ExitCode::FAILURE
+                }
+                Ok(source) if arity > 2 => {
+                    check_dary(source, arity);
+                    ExitCode::SUCCESS
+                }
+                Ok(source) => {
+                    check(source, max_trees);
+                    ExitCode::SUCCESS
+                }
+                Err(error) => {
+                    eprintln!("{}", error);
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        Command::Batch { path } => match read_sources(&path) {
+            Ok(sources) => {
+                batch(sources);
+                ExitCode::SUCCESS
+            }
+            Err(error) => {
+                eprintln!("{}", error);
+                ExitCode::FAILURE
+            }
+        },
+        Command::Compress { path } => match std::fs::read(&path) {
+            Ok(bytes) => {
+                compress(&bytes);
+                ExitCode::SUCCESS
+            }
+            Err(error) => {
+                eprintln!("Could not read {}: {}", path.display(), error);
+                ExitCode::FAILURE
+            }
+        },
+        Command::Show { path, dot } => match Counterexample::load(&path) {
+            Ok(counterexample) if dot => {
+                print!("{}", to_dot(&counterexample.witness_tree, true));
+                ExitCode::SUCCESS
+            }
+            Ok(counterexample) => {
+                show(&counterexample);
+                ExitCode::SUCCESS
+            }
+            Err(error) => {
+                eprintln!("Could not read {}: {}", path.display(), error);
+                ExitCode::FAILURE
+            }
+        },
+        Command::Run {
+            conjecture,
+            min_size,
+            max_size,
+            sources,
+            exhaustive,
+            workers,
+            seed,
+            distribution,
+            output,
+        } => {
+            let Some(conjecture) = find_conjecture(&conjecture) else {
+                eprintln!("Unknown conjecture: {}", conjecture);
+                return ExitCode::FAILURE;
+            };
+            if let Err(error) = check_run_arguments(min_size, max_size, workers)
+            {
+                eprintln!("{}", error);
+                return ExitCode::FAILURE;
+            }
+            let generator = SourceGenerator::new(
+                seed.unwrap_or_else(|| thread_rng().gen()),
+            )
+            .with_distribution(distribution);
+            println!(
+                "Seed: {}, distribution: {}",
+                generator.seed(),
+                distribution
+            );
+            let pool = ThreadPoolBuilder::new()
+                .num_threads(workers)
+                .build()
+                .unwrap();
+            let counterexample = pool.install(|| {
+                run(
+                    conjecture.as_ref(),
+                    min_size..=max_size,
+                    sources,
+                    exhaustive,
+                    &generator,
+                )
+            });
+            let Some(counterexample) = counterexample else {
+                println!("No counterexample found");
+                return ExitCode::SUCCESS;
+            };
+            match counterexample.source_seed {
+                Some(source_seed) => println!(
+                    "Found counterexample at seed {}, index {}, distribution {}",
+                    source_seed.seed, source_seed.index, source_seed.distribution
+                ),
+                None => println!(
+                    "Found counterexample: {:?}",
+                    counterexample.source
+                ),
+            }
+            if let Err(error) = counterexample.save(&output) {
+                eprintln!("Could not write {}: {}", output.display(), error);
+                return ExitCode::FAILURE;
+            }
+            println!("Written to {}", output.display());
+            ExitCode::SUCCESS
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn cli_test() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn check_run_arguments_test() {
+        assert!(check_run_arguments(7, 8, 8).is_ok());
+        assert!(check_run_arguments(5, 5, 1).is_ok());
+        for (min_size, max_size) in [(2, 2), (3, 8), (4, 4), (8, 7)] {
+            assert!(check_run_arguments(min_size, max_size, 1).is_err());
         }
+        assert!(check_run_arguments(7, 8, 0).is_err());
     }
 }
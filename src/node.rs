@@ -1,29 +1,47 @@
+use crate::code::Code;
+use crate::source::Symbol;
+
+use itertools::Itertools;
+use num::Zero;
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::hash::Hash;
 use std::ops::*;
 use std::sync::Arc;
 
 pub trait RealNum:
-    Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Copy + Ord + Hash
+    Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Clone
+    + Ord
+    + Hash
+    + Zero
 where
     Self: std::marker::Sized,
 {
 }
 impl<T> RealNum for T where
-    T: Add<Output = T> + Sub<Output = T> + Mul<Output = T> + Copy + Ord + Hash
+    T: Add<Output = T>
+        + Sub<Output = T>
+        + Mul<Output = T>
+        + Clone
+        + Ord
+        + Hash
+        + Zero
 {
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum NodeType<T>
 where
     T: RealNum,
 {
-    Leaf(char),
+    Leaf(Symbol),
     Branch(Arc<[Node<T>; 2]>),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Node<T>
 where
     T: RealNum,
@@ -32,6 +50,80 @@ where
     node_type: NodeType<T>,
 }
 
+//How Node::new_huffman_with chooses between nodes of equal probability
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TieBreak {
+    //Smallest weighted path length first, which gives the Huffman code of
+    //minimum length variance
+    MinVariance,
+    //Tallest first, which gives the deepest Huffman tree
+    Skinniest,
+    //Shortest first, which gives the shallowest Huffman tree
+    MinMaxDepth,
+    //Smallest symbol first
+    Lexicographic,
+    //Oldest first, so merged nodes go after every tied input node
+    MergeNewestLast,
+}
+
+//A node waiting to be merged, with what the tie-break policies look at
+struct Candidate<T>
+where
+    T: RealNum,
+{
+    node: Node<T>,
+    height: usize,
+    path_length: T,
+    smallest_symbol: Symbol,
+    created: usize,
+}
+
+impl<T> Candidate<T>
+where
+    T: RealNum,
+{
+    fn cmp_tie(&self, other: &Candidate<T>, tie_break: TieBreak) -> Ordering {
+        match tie_break {
+            TieBreak::MinVariance => self.path_length.cmp(&other.path_length),
+            TieBreak::Skinniest => other.height.cmp(&self.height),
+            TieBreak::MinMaxDepth => self.height.cmp(&other.height),
+            TieBreak::Lexicographic => {
+                self.smallest_symbol.cmp(&other.smallest_symbol)
+            }
+            TieBreak::MergeNewestLast => self.created.cmp(&other.created),
+        }
+    }
+}
+
+//Ways a tree can fail Gallager's sibling property, which holds exactly for
+//the trees Huffman's algorithm can build
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SiblingViolation<T> {
+    //A branch whose probability isn't the sum of its children's
+    UnbalancedBranch {
+        probability: T,
+        children: T,
+    },
+    //Two sibling pairs that can't both be listed side by side in
+    //non-increasing order, because the lighter node of `heavier_pair`
+    //weighs less than the heavier node of `lighter_pair`
+    Interleaved {
+        heavier_pair: (T, T),
+        lighter_pair: (T, T),
+    },
+}
+
+//Preorder listing of a tree with the children of every branch ordered by
+//their own listings, so trees that differ only by swapping children have
+//the same canonical form
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CanonicalToken<T> {
+    Branch,
+    Leaf(Symbol, T),
+}
+
 impl<T> PartialEq for Node<T>
 where
     T: RealNum,
@@ -65,28 +157,50 @@ impl<T> Node<T>
 where
     T: RealNum,
 {
-    pub fn new_leaf(probability: T, symbol: char) -> Node<T>
+    pub fn new_leaf<S: Into<Symbol>>(probability: T, symbol: S) -> Node<T>
     where
         T: RealNum,
     {
         Node {
             probability,
-            node_type: NodeType::Leaf(symbol),
+            node_type: NodeType::Leaf(symbol.into()),
         }
     }
     pub fn new_branch(left: Node<T>, right: Node<T>) -> Node<T> {
         Node {
-            probability: left.probability + right.probability,
+            probability: left.probability.clone() + right.probability.clone(),
             node_type: NodeType::Branch(Arc::new([left, right])),
         }
     }
     pub fn probability(&self) -> T {
-        self.probability
+        self.probability.clone()
     }
     pub fn node_type(&self) -> &NodeType<T> {
         &self.node_type
     }
 
+    #[allow(dead_code)]
+    pub fn canonical_form(&self) -> Vec<CanonicalToken<T>> {
+        match &self.node_type {
+            NodeType::Leaf(symbol) => {
+                vec![CanonicalToken::Leaf(symbol.clone(), self.probability())]
+            }
+            NodeType::Branch(children) => {
+                let left = children[0].canonical_form();
+                let right = children[1].canonical_form();
+                let (first, second) = if left <= right {
+                    (left, right)
+                } else {
+                    (right, left)
+                };
+                let mut form = vec![CanonicalToken::Branch];
+                form.extend(first);
+                form.extend(second);
+                form
+            }
+        }
+    }
+
     //Used for testing only, very slow
     #[allow(dead_code)]
     pub fn is_same_as(&self, other: &Node<T>) -> bool {
@@ -106,65 +220,380 @@ where
             (_, _) => false,
         }
     }
-}
 
-impl Node<u32> {
     //Checks whether any node is
     pub fn is_probably_competitively_optimal(&self) -> bool {
-        fn helper(
-            node: &Node<u32>,
-            mut higher_node_differences: Vec<u32>,
-            prev_sibling_difference: u32,
-        ) -> bool {
-            match node.node_type.clone() {
-                NodeType::Leaf(_) => higher_node_differences
-                    .into_iter()
-                    .all(|p| p <= node.probability),
+        self.flagged_leaves().is_empty()
+    }
+
+    //The leaves that fail is_probably_competitively_optimal's check, by
+    //weighing less than the difference between some pair of siblings above
+    //them
+    pub fn flagged_leaves(&self) -> Vec<Symbol> {
+        fn helper<T: RealNum>(
+            node: &Node<T>,
+            mut higher_node_differences: Vec<T>,
+            prev_sibling_difference: T,
+            flagged: &mut Vec<Symbol>,
+        ) {
+            match &node.node_type {
+                NodeType::Leaf(symbol) => {
+                    if higher_node_differences
+                        .into_iter()
+                        .any(|p| p > node.probability)
+                    {
+                        flagged.push(symbol.clone());
+                    }
+                }
                 NodeType::Branch(children) => {
-                    //let (l, r) = (children[0].clone(), children[1].clone());
                     let (l, r) = (&children[0], &children[1]);
                     let (bigger, smaller) =
                         if *l > *r { (l, r) } else { (r, l) };
-                    let sibling_difference =
-                        bigger.probability - smaller.probability;
-                    let one_child_optimal = helper(
+                    let sibling_difference = bigger.probability.clone()
+                        - smaller.probability.clone();
+                    helper(
                         smaller,
                         higher_node_differences.clone(),
-                        sibling_difference,
+                        sibling_difference.clone(),
+                        flagged,
                     );
                     higher_node_differences.push(prev_sibling_difference);
-                    let other_child_optimal = helper(
+                    helper(
                         bigger,
                         higher_node_differences,
                         sibling_difference,
+                        flagged,
                     );
-                    one_child_optimal && other_child_optimal
                 }
             }
         }
-        helper(self, vec![], u32::MIN)
+        let mut flagged = vec![];
+        helper(self, vec![], T::zero(), &mut flagged);
+        flagged
     }
 
+    //Checks that every node but the root can be listed in non-increasing
+    //probability with siblings next to each other. Pairs are listed heaviest
+    //first, so it's enough that each pair's lighter node outweighs the next
+    //pair's heavier one.
     #[allow(dead_code)]
-    pub fn new_huffman(mut nodes: Vec<Node<u32>>) -> Option<Node<u32>> {
+    pub fn sibling_property(&self) -> Result<(), SiblingViolation<T>> {
+        let mut pairs = vec![];
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            let NodeType::Branch(children) = &node.node_type else {
+                continue;
+            };
+            let [left, right] = &**children;
+            let children = left.probability.clone() + right.probability.clone();
+            if children != node.probability {
+                return Err(SiblingViolation::UnbalancedBranch {
+                    probability: node.probability.clone(),
+                    children,
+                });
+            }
+            let (heavier, lighter) = if left >= right {
+                (left, right)
+            } else {
+                (right, left)
+            };
+            pairs.push((heavier.probability(), lighter.probability()));
+            stack.push(left);
+            stack.push(right);
+        }
+        pairs.sort_by(|a, b| b.cmp(a));
+        for (heavier_pair, lighter_pair) in pairs.into_iter().tuple_windows() {
+            if heavier_pair.1 < lighter_pair.0 {
+                return Err(SiblingViolation::Interleaved {
+                    heavier_pair,
+                    lighter_pair,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    //Builds the tree with the code's lengths that pairs the lightest nodes
+    //at every depth, which has the sibling property if any such tree does.
+    //None if the lengths don't form a full binary tree.
+    #[allow(dead_code)]
+    pub fn from_code(code: &Code<T>) -> Option<Node<T>> {
+        let max_depth = *code.values().max()? as usize;
+        let mut levels: Vec<Vec<Node<T>>> = vec![vec![]; max_depth + 1];
+        for (code_word, &depth) in code.iter() {
+            levels[depth as usize].push(Node::new_leaf(
+                code_word.probability().clone(),
+                code_word.source_symbol().clone(),
+            ));
+        }
+        let mut branches = vec![];
+        for depth in (1..=max_depth).rev() {
+            let mut nodes = std::mem::take(&mut levels[depth]);
+            nodes.append(&mut branches);
+            if !nodes.len().is_multiple_of(2) {
+                return None;
+            }
+            nodes.sort();
+            branches = nodes
+                .into_iter()
+                .tuples()
+                .map(|(left, right)| Node::new_branch(left, right))
+                .collect_vec();
+        }
+        let mut roots = std::mem::take(&mut levels[0]);
+        roots.append(&mut branches);
+        match roots.len() {
+            1 => roots.pop(),
+            _ => None,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn new_huffman(mut nodes: Vec<Node<T>>) -> Option<Node<T>> {
         loop {
             match nodes.len() {
                 0 => return None,
                 1 => return Some(nodes[0].clone()),
                 _ => {
                     nodes.sort_by(|a, b| b.cmp(a));
-                    let l: Node<u32> = nodes.pop().unwrap();
-                    let r: Node<u32> = nodes.pop().unwrap();
+                    let l: Node<T> = nodes.pop().unwrap();
+                    let r: Node<T> = nodes.pop().unwrap();
                     nodes.push(Node::new_branch(l, r))
                 }
             }
         }
     }
+
+    //Huffman's algorithm with ties between the smallest nodes broken by
+    //`tie_break`. Input nodes are treated as leaves labeled by their
+    //smallest symbol.
+    pub fn new_huffman_with(
+        nodes: Vec<Node<T>>,
+        tie_break: TieBreak,
+    ) -> Option<Node<T>> {
+        let mut candidates = nodes
+            .into_iter()
+            .enumerate()
+            .map(|(created, node)| Candidate {
+                smallest_symbol: node.smallest_symbol(),
+                node,
+                height: 0,
+                path_length: T::zero(),
+                created,
+            })
+            .collect_vec();
+        let mut created = candidates.len();
+        while candidates.len() > 1 {
+            candidates.sort_by(|a, b| {
+                b.node.cmp(&a.node).then_with(|| b.cmp_tie(a, tie_break))
+            });
+            let l = candidates.pop().unwrap();
+            let r = candidates.pop().unwrap();
+            let node = Node::new_branch(l.node, r.node);
+            candidates.push(Candidate {
+                height: l.height.max(r.height) + 1,
+                path_length: l.path_length + r.path_length + node.probability(),
+                smallest_symbol: l.smallest_symbol.min(r.smallest_symbol),
+                node,
+                created,
+            });
+            created += 1;
+        }
+        candidates.pop().map(|candidate| candidate.node)
+    }
+
+    fn smallest_symbol(&self) -> Symbol {
+        match &self.node_type {
+            NodeType::Leaf(symbol) => symbol.clone(),
+            NodeType::Branch(children) => children[0]
+                .smallest_symbol()
+                .min(children[1].smallest_symbol()),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn height(&self) -> usize {
+        match &self.node_type {
+            NodeType::Leaf(_) => 0,
+            NodeType::Branch(children) => {
+                children[0].height().max(children[1].height()) + 1
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::code::{possible_codes, possible_length_profiles, FromNode};
+    use crate::reduction::{all_possible_reductions, distinct_huffman_trees};
+    use crate::source::Source;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn canonical_form_test() {
+        let (a, b, c) = (
+            Node::new_leaf(1, 'a'),
+            Node::new_leaf(1, 'b'),
+            Node::new_leaf(2, 'c'),
+        );
+        let tree =
+            Node::new_branch(c.clone(), Node::new_branch(a.clone(), b.clone()));
+        let mirrored =
+            Node::new_branch(Node::new_branch(b.clone(), a.clone()), c.clone());
+        let relabeled =
+            Node::new_branch(b.clone(), Node::new_branch(a.clone(), c.clone()));
+        assert_eq!(tree.canonical_form(), mirrored.canonical_form());
+        assert_ne!(tree.canonical_form(), relabeled.canonical_form());
+        assert_eq!(tree.canonical_form().len(), 5);
+    }
+
+    #[test]
+    fn new_huffman_with_test() {
+        let mut rng = StdRng::seed_from_u64(29);
+        for size in 2..=9 {
+            for _ in 0..10 {
+                let weights = (0..size).map(|_| rng.gen_range(1..=4));
+                let source = Source::from_weights(weights.collect_vec());
+                let trees = distinct_huffman_trees(source.to_leaves_vec());
+                let heights = trees.iter().map(Node::height).collect_vec();
+                let square_length = |tree: &Node<u32>| -> u32 {
+                    Code::from_node(tree)
+                        .iter()
+                        .map(|(code_word, &depth)| {
+                            code_word.probability() * (depth as u32).pow(2)
+                        })
+                        .sum()
+                };
+                let huffman_with = |tie_break| {
+                    let tree = Node::new_huffman_with(
+                        source.to_leaves_vec(),
+                        tie_break,
+                    )
+                    .unwrap();
+                    assert_eq!(tree.sibling_property(), Ok(()));
+                    tree
+                };
+                assert_eq!(
+                    huffman_with(TieBreak::Skinniest).height(),
+                    *heights.iter().max().unwrap()
+                );
+                assert_eq!(
+                    huffman_with(TieBreak::MinMaxDepth).height(),
+                    *heights.iter().min().unwrap()
+                );
+                assert_eq!(
+                    square_length(&huffman_with(TieBreak::MinVariance)),
+                    trees.iter().map(square_length).min().unwrap()
+                );
+                huffman_with(TieBreak::Lexicographic);
+                huffman_with(TieBreak::MergeNewestLast);
+            }
+        }
+
+        //a and b merge first, then the lexicographic policy takes c
+        //before d, and merging newest last takes both before ab
+        let leaves = vec![
+            Node::new_leaf(1, 'a'),
+            Node::new_leaf(1, 'b'),
+            Node::new_leaf(2, 'd'),
+            Node::new_leaf(2, 'c'),
+        ];
+        let lexicographic =
+            Node::new_huffman_with(leaves.clone(), TieBreak::Lexicographic)
+                .unwrap();
+        let expected = Node::new_branch(
+            leaves[2].clone(),
+            Node::new_branch(
+                Node::new_branch(leaves[0].clone(), leaves[1].clone()),
+                leaves[3].clone(),
+            ),
+        );
+        assert!(lexicographic.is_same_as(&expected));
+        let newest_last =
+            Node::new_huffman_with(leaves.clone(), TieBreak::MergeNewestLast)
+                .unwrap();
+        assert_eq!(newest_last.height(), 2);
+        assert!(Node::<u32>::new_huffman_with(vec![], TieBreak::Skinniest)
+            .is_none());
+    }
+
+    #[test]
+    fn sibling_property_test() {
+        let mut rng = StdRng::seed_from_u64(13);
+        for size in 2..=8 {
+            let source = Source::new(size, &mut rng);
+            for tree in all_possible_reductions(source.to_leaves_vec()) {
+                assert_eq!(tree.sibling_property(), Ok(()));
+            }
+        }
+
+        //1 is paired with 3 + 2, but 3 and 2 outweigh it
+        let skewed = Node::new_branch(
+            Node::new_branch(Node::new_leaf(3, 'c'), Node::new_leaf(2, 'b')),
+            Node::new_leaf(1, 'a'),
+        );
+        assert_eq!(
+            skewed.sibling_property(),
+            Err(SiblingViolation::Interleaved {
+                heavier_pair: (5, 1),
+                lighter_pair: (3, 2),
+            })
+        );
+
+        let unbalanced = Node {
+            probability: 4,
+            node_type: NodeType::Branch(Arc::new([
+                Node::new_leaf(1, 'a'),
+                Node::new_leaf(2, 'b'),
+            ])),
+        };
+        assert_eq!(
+            unbalanced.sibling_property(),
+            Err(SiblingViolation::UnbalancedBranch {
+                probability: 4,
+                children: 3,
+            })
+        );
+    }
+
+    #[test]
+    fn from_code_test() {
+        //Small weights, so that there are many ties and Huffman codes
+        let mut rng = StdRng::seed_from_u64(17);
+        for size in 2..=7 {
+            for _ in 0..5 {
+                let weights = (0..size).map(|_| rng.gen_range(1..=6));
+                let source = Source::from_weights(weights.collect_vec());
+                let huffman_codes =
+                    all_possible_reductions(source.to_leaves_vec())
+                        .iter()
+                        .map(Code::from_node)
+                        .collect_vec();
+                for code in
+                    possible_codes(source, possible_length_profiles(size))
+                {
+                    let tree = Node::from_code(&code).unwrap();
+                    assert_eq!(Code::from_node(&tree), code);
+                    assert_eq!(
+                        tree.sibling_property().is_ok(),
+                        huffman_codes.contains(&code)
+                    );
+                }
+            }
+        }
+        let mut code = Code::from_node(
+            &Node::new_huffman(vec![
+                Node::new_leaf(1, 'a'),
+                Node::new_leaf(1, 'b'),
+                Node::new_leaf(1, 'c'),
+            ])
+            .unwrap(),
+        );
+        code.values_mut().for_each(|depth| *depth = 2);
+        assert!(Node::from_code(&code).is_none());
+    }
 
     #[test]
     fn new_huffman_test() {
@@ -187,7 +616,7 @@ mod tests {
 
     #[test]
     fn is_same_as_test() {
-        let leaves = vec![
+        let leaves = [
             Node::new_leaf(1, 'a'),
             Node::new_leaf(1, 'b'),
             Node::new_leaf(2, 'c'),
@@ -319,6 +748,7 @@ mod tests {
             ),
         );
         assert!(!code_a.is_probably_competitively_optimal());
+        assert_eq!(code_a.flagged_leaves(), vec![Symbol::from('d')]);
         println!("Starting code b");
         assert!(code_b.is_probably_competitively_optimal());
         assert!(code_c.is_probably_competitively_optimal());
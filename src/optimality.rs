@@ -0,0 +1,309 @@
+use crate::code::{
+    arrange_against, Advantage, CandidateCodes, Code, CodeWord, CompetitiveOrd,
+    Depth, New,
+};
+use crate::dense::DenseSource;
+use crate::node::RealNum;
+
+use rayon::prelude::*;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+//Decides whether any prefix code competitively beats `code`, without
+//enumerating candidate codes, and returns the candidate with the largest
+//competitive advantage over it if so.
+//
+//Against a fixed code only the sign of each length difference matters, so a
+//candidate either shortens a symbol by exactly one (a win), keeps its length
+//(a tie) or hangs it below every other code word (a loss). All losers fit in
+//a single spare node one level below the deepest code word, which exists
+//whenever the remaining code words leave any Kraft slack. Within a depth the
+//most probable symbols win and the least probable lose, so the search is a
+//dynamic program over depths, deepest first, on the number of nodes at the
+//current depth needed to hold everything chosen below it.
+pub fn beating_code<T>(code: &Code<T>) -> Option<Code<T>>
+where
+    T: RealNum,
+{
+    beating_dary_code(code, 2)
+}
+
+//The same search for codes over an alphabet of `arity` letters, where a
+//winner takes up `arity` nodes one level below its new depth
+pub fn beating_dary_code<T>(code: &Code<T>, arity: u64) -> Option<Code<T>>
+where
+    T: RealNum,
+{
+    assert!(arity >= 2);
+    if code.len() < 2 {
+        return None;
+    }
+    let max_depth = *code.values().max().unwrap() as usize;
+    let mut levels: Vec<Vec<&CodeWord<T>>> = vec![vec![]; max_depth + 1];
+    for (code_word, &depth) in code.iter() {
+        levels[depth as usize].push(code_word);
+    }
+    for level in levels.iter_mut() {
+        level.sort_by(|a, b| b.probability().cmp(a.probability()));
+    }
+
+    [false, true]
+        .into_iter()
+        .filter_map(|with_losers| best_choice(&levels, arity, with_losers))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .filter(|(gain, _)| gain.signum() == Ordering::Greater)
+        .map(|(_, choices)| build_code(&levels, arity, &choices))
+}
+
+//Searches every candidate code, arranged against `code`, for one that beats
+//it, in parallel and stopping at the first found. Exponentially slower than
+//`beating_code`, but shares nothing with it, so it's used to confirm results
+//on small sources.
+#[allow(dead_code)]
+pub fn beating_code_by_enumeration<T>(code: &Code<T>) -> Option<Code<T>>
+where
+    T: RealNum + Send + Sync,
+{
+    let candidates = CandidateCodes::new(DenseSource::from_code(code));
+    let source = candidates.source();
+    candidates
+        .par_codes()
+        .filter_map(|other| arrange_against(&source.to_code(&other), code))
+        .find_any(|other| other.beats(code) == Some(true))
+}
+
+//Number of winners and losers chosen at each depth
+type Choices = Vec<(usize, usize)>;
+
+//The best (gain, state one level deeper, choice) for every reachable number
+//of nodes needed at one depth
+type Step<T> = HashMap<u64, (Advantage<T>, u64, (usize, usize))>;
+
+fn best_choice<T>(
+    levels: &[Vec<&CodeWord<T>>],
+    arity: u64,
+    with_losers: bool,
+) -> Option<(Advantage<T>, Choices)>
+where
+    T: RealNum,
+{
+    let max_depth = levels.len() - 1;
+    let mut steps: Vec<Step<T>> = vec![HashMap::new(); max_depth + 1];
+    let mut deeper: HashMap<u64, Advantage<T>> = HashMap::new();
+    deeper.insert(with_losers as u64, Advantage::zero());
+
+    for depth in (1..=max_depth).rev() {
+        let level = &levels[depth];
+        let mut prefix = vec![T::zero()];
+        for code_word in level.iter() {
+            let total = prefix.last().unwrap().clone();
+            prefix.push(total + code_word.probability().clone());
+        }
+        let total = prefix[level.len()].clone();
+        let max_winners = if depth >= 2 { level.len() } else { 0 };
+        let max_losers = if with_losers { level.len() } else { 0 };
+
+        let mut current: Step<T> = HashMap::new();
+        for (&deeper_state, deeper_gain) in deeper.iter() {
+            for winners in 0..=max_winners {
+                for losers in 0..=max_losers.min(level.len() - winners) {
+                    let ties = level.len() - winners - losers;
+                    let state = deeper_state.div_ceil(arity)
+                        + arity * winners as u64
+                        + ties as u64;
+                    let capacity = arity.checked_pow(depth as u32);
+                    if capacity.is_some_and(|capacity| state > capacity) {
+                        continue;
+                    }
+                    let gain = Advantage {
+                        gained: deeper_gain.gained.clone()
+                            + prefix[winners].clone(),
+                        lost: deeper_gain.lost.clone() + total.clone()
+                            - prefix[level.len() - losers].clone(),
+                    };
+                    let choice = (gain, deeper_state, (winners, losers));
+                    match current.get(&state) {
+                        Some((best, _, _)) if *best >= choice.0 => (),
+                        _ => {
+                            current.insert(state, choice);
+                        }
+                    }
+                }
+            }
+        }
+        deeper = current
+            .iter()
+            .map(|(&state, (gain, _, _))| (state, gain.clone()))
+            .collect();
+        steps[depth] = current;
+    }
+
+    let (&state, (gain, _, _)) = steps[1]
+        .iter()
+        .filter(|(&state, _)| state.div_ceil(arity) <= 1)
+        .max_by(|(_, (a, _, _)), (_, (b, _, _))| a.cmp(b))?;
+    let gain = gain.clone();
+    let mut choices = vec![(0, 0); max_depth + 1];
+    let mut state = state;
+    for depth in 1..=max_depth {
+        let (_, deeper_state, choice) = steps[depth][&state].clone();
+        choices[depth] = choice;
+        state = deeper_state;
+    }
+    Some((gain, choices))
+}
+
+fn build_code<T>(
+    levels: &[Vec<&CodeWord<T>>],
+    arity: u64,
+    choices: &Choices,
+) -> Code<T>
+where
+    T: RealNum,
+{
+    let max_depth = levels.len() - 1;
+    let num_losers: u64 =
+        choices.iter().map(|&(_, losers)| losers as u64).sum();
+    //Losers share the spare node at max_depth + 1
+    let mut loser_depth = max_depth + 1;
+    let mut capacity = 1;
+    while capacity < num_losers {
+        capacity *= arity;
+        loser_depth += 1;
+    }
+    let mut code = Code::new();
+    for (depth, level) in levels.iter().enumerate() {
+        let (winners, losers) = choices[depth];
+        for (i, &code_word) in level.iter().enumerate() {
+            let new_depth = if i < winners {
+                depth - 1
+            } else if i >= level.len() - losers {
+                loser_depth
+            } else {
+                depth
+            };
+            code.insert(code_word.clone(), new_depth as Depth);
+        }
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::test_helpers::kraft_sum_fits;
+    use crate::code::{possible_codes, possible_length_profiles, FromNode};
+    use crate::node::Node;
+    use crate::reduction::all_possible_reductions;
+    use crate::source::Source;
+    use itertools::Itertools;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn beating_code_matches_brute_force_test() {
+        let mut rng = StdRng::seed_from_u64(11);
+        for size in 2..=6 {
+            let profiles = possible_length_profiles(size).collect_vec();
+            for _ in 0..10 {
+                let source = Source::new(size, &mut rng);
+                let codes =
+                    possible_codes(source, profiles.clone()).collect_vec();
+                for code in codes.iter().take(40) {
+                    let beaten =
+                        codes.iter().any(|other| other.beats(code).unwrap());
+                    match beating_code(code) {
+                        Some(better) => {
+                            assert!(beaten);
+                            assert!(better.beats(code).unwrap());
+                            assert!(kraft_sum_fits(&better, 2));
+                            let best = codes
+                                .iter()
+                                .map(|other| {
+                                    other.competitive_advantage(code).unwrap()
+                                })
+                                .max()
+                                .unwrap();
+                            assert!(
+                                better.competitive_advantage(code).unwrap()
+                                    >= best
+                            );
+                        }
+                        None => assert!(!beaten),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn beating_code_matches_candidate_codes_test() {
+        //Few distinct weights, so classes are large and enumeration is cheap
+        let mut rng = StdRng::seed_from_u64(5);
+        for size in 7..=8 {
+            for _ in 0..4 {
+                let weights = (0..size).map(|_| rng.gen_range(1..=4));
+                let source = Source::from_weights(weights.collect_vec());
+                let candidates =
+                    CandidateCodes::new(DenseSource::new(source.clone()));
+                let candidates = candidates
+                    .codes()
+                    .map(|other| candidates.source().to_code(&other))
+                    .collect_vec();
+                for tree in all_possible_reductions(source.to_leaves_vec()) {
+                    let code = Code::from_node(&tree);
+                    let beaten = candidates.iter().any(|other| {
+                        arrange_against(other, &code)
+                            .unwrap()
+                            .beats(&code)
+                            .unwrap()
+                    });
+                    assert_eq!(beating_code(&code).is_some(), beaten);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn beating_code_by_enumeration_test() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for size in 2..=8 {
+            let source = Source::new(size, &mut rng);
+            let candidates =
+                CandidateCodes::new(DenseSource::new(source.clone()));
+            assert!(
+                candidates.codes().count()
+                    <= possible_length_profiles(size).count()
+            );
+            for tree in all_possible_reductions(source.to_leaves_vec()) {
+                let code = Code::from_node(&tree);
+                let by_enumeration = beating_code_by_enumeration(&code);
+                assert_eq!(
+                    by_enumeration.is_some(),
+                    beating_code(&code).is_some()
+                );
+                if let Some(better) = by_enumeration {
+                    assert!(better.beats(&code).unwrap());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn beating_code_large_source_test() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let source = Source::new(25, &mut rng);
+        let huffman = Code::from_node(
+            &Node::new_huffman(source.to_leaves_vec()).unwrap(),
+        );
+        if let Some(better) = beating_code(&huffman) {
+            assert!(better.beats(&huffman).unwrap());
+            assert!(kraft_sum_fits(&better, 2));
+        }
+        //A code that wastes its whole right subtree is beaten
+        let mut code = Code::new();
+        code.insert(CodeWord::new('a', 1), 2);
+        code.insert(CodeWord::new('b', 1), 2);
+        assert!(beating_code(&code).unwrap().beats(&code).unwrap());
+    }
+}
@@ -0,0 +1,645 @@
+use crate::code::{Code, Depth, FromNode};
+use crate::node::{Node, RealNum};
+
+use itertools::Itertools;
+use num::{BigUint, One, Zero};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+use std::iter::{once, repeat, zip};
+use std::sync::Arc;
+
+fn remove_two<T>(x: usize, y: usize, vec: &mut Vec<T>) -> (T, T) {
+    assert!(x != y);
+    let (first, second) = if x < y { (x, y) } else { (y, x) };
+
+    let j = vec.remove(first);
+    let k = vec.remove(second - 1);
+    (j, k)
+}
+
+fn join_nodes_by_indices<T>(
+    pair_index: (usize, usize),
+    mut nodes: Vec<Node<T>>,
+) -> Vec<Node<T>>
+where
+    T: RealNum,
+{
+    let (left, right) = remove_two(pair_index.0, pair_index.1, &mut nodes);
+    nodes.push(Node::new_branch(left, right));
+    nodes
+}
+
+fn possible_reductions<T>(mut nodes: Vec<Node<T>>) -> Vec<Vec<Node<T>>>
+where
+    T: RealNum,
+{
+    //TODO: Add better error handling
+    assert!(!nodes.is_empty());
+    nodes.sort();
+    let num_smallest_nodes =
+        nodes.iter().take_while(|&node| *node == nodes[0]).count();
+    let num_second_smallest_nodes = nodes[num_smallest_nodes..]
+        .iter()
+        .take_while(|&node| *node == nodes[num_smallest_nodes])
+        .count();
+
+    let possible_pair_indices: Box<dyn Iterator<Item = (usize, usize)>> =
+        match (num_smallest_nodes, num_second_smallest_nodes) {
+            (0, _) => panic!("Impossible because of the length assertion"),
+            (1, 0) => panic!("Impossible because of the length assertion"),
+            (1, 1) => Box::new(once((0, 1))),
+            (1, n) => Box::new(zip(repeat(0usize), 1..(n + 1))),
+            (n, _) => Box::new((0..n).tuple_combinations::<(_, _)>()),
+        };
+
+    possible_pair_indices
+        .map(|pair| join_nodes_by_indices(pair, nodes.clone()))
+        .collect()
+}
+
+//Note that this breaks if there exists a zero probablity element
+#[allow(dead_code)]
+pub fn all_possible_reductions<T>(nodes: Vec<Node<T>>) -> Vec<Node<T>>
+where
+    T: RealNum,
+{
+    let mut partial_reductions = vec![nodes];
+    let mut completed_reductions: Vec<_> = vec![];
+    while !partial_reductions.is_empty() {
+        (completed_reductions, partial_reductions) = partial_reductions
+            .into_iter()
+            .partition(|nodes| nodes.len() < 2);
+        partial_reductions = partial_reductions
+            .into_iter()
+            .flat_map(|nodes_list| possible_reductions(nodes_list))
+            .collect_vec();
+    }
+    completed_reductions.into_iter().flatten().collect_vec()
+}
+
+//Reductions of `nodes` that differ in `key`, each yielded once. Partial
+//reductions are deduplicated by the sorted keys of their nodes at every
+//step, so keys must be unchanged by reordering children.
+fn distinct_reductions_by<T, K>(
+    nodes: Vec<Node<T>>,
+    key: impl Fn(&Node<T>) -> K,
+) -> Vec<Node<T>>
+where
+    T: RealNum,
+    K: Ord + Hash,
+{
+    let mut partial_reductions = vec![nodes];
+    let mut completed_reductions: Vec<_> = vec![];
+    while !partial_reductions.is_empty() {
+        (completed_reductions, partial_reductions) = partial_reductions
+            .into_iter()
+            .partition(|nodes| nodes.len() < 2);
+        partial_reductions = partial_reductions
+            .into_iter()
+            .flat_map(|nodes_list| possible_reductions(nodes_list))
+            .unique_by(|nodes| nodes.iter().map(&key).sorted().collect_vec())
+            .collect_vec();
+    }
+    completed_reductions
+        .into_iter()
+        .flatten()
+        .unique_by(key)
+        .collect_vec()
+}
+
+//A tree over the positions of a sorted list of weights, with the children
+//of every merge ordered so equal templates are structurally equal
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Template {
+    Slot(usize),
+    Merge(Arc<Template>, Arc<Template>),
+}
+
+fn merge(a: Arc<Template>, b: Arc<Template>) -> Arc<Template> {
+    if a <= b {
+        Arc::new(Template::Merge(a, b))
+    } else {
+        Arc::new(Template::Merge(b, a))
+    }
+}
+
+//Replaces the slots of a template, sharing the result for subtemplates that
+//are shared
+fn substitute(
+    template: &Arc<Template>,
+    slots: &[Arc<Template>],
+    cache: &mut HashMap<*const Template, Arc<Template>>,
+) -> Arc<Template> {
+    if let Some(substituted) = cache.get(&Arc::as_ptr(template)) {
+        return substituted.clone();
+    }
+    let substituted = match &**template {
+        Template::Slot(slot) => slots[*slot].clone(),
+        Template::Merge(a, b) => {
+            merge(substitute(a, slots, cache), substitute(b, slots, cache))
+        }
+    };
+    cache.insert(Arc::as_ptr(template), substituted.clone());
+    substituted
+}
+
+type Templates = Arc<Vec<Arc<Template>>>;
+
+//Every distinct Huffman tree over sorted `weights`. Which nodes Huffman's
+//algorithm may merge depends only on their weights, so the trees are
+//memoized by the multiset of weights left after each merge and shared by
+//every reduction that reaches it.
+fn huffman_templates<T>(
+    weights: &[T],
+    memo: &mut HashMap<Vec<T>, Templates>,
+) -> Templates
+where
+    T: RealNum,
+{
+    if let Some(templates) = memo.get(weights) {
+        return templates.clone();
+    }
+    if weights.len() < 2 {
+        let templates = Arc::new(vec![Arc::new(Template::Slot(0))]);
+        memo.insert(weights.to_vec(), templates.clone());
+        return templates;
+    }
+    let smallest = weights.iter().take_while(|&w| *w == weights[0]).count();
+    let second_smallest = weights[smallest..]
+        .iter()
+        .take_while(|&w| *w == weights[smallest])
+        .count();
+    let pairs = match smallest {
+        1 => (1..=second_smallest).map(|j| (0, j)).collect_vec(),
+        _ => (0..smallest).tuple_combinations().collect_vec(),
+    };
+
+    let mut templates = vec![];
+    for (i, j) in pairs {
+        let merged = weights[i].clone() + weights[j].clone();
+        let mut rest = (0..weights.len()).filter(|&k| k != i && k != j);
+        let mut reduced =
+            rest.clone().map(|k| weights[k].clone()).collect_vec();
+        let position = reduced.partition_point(|w| *w < merged);
+        reduced.insert(position, merged);
+
+        let mut slots = vec![];
+        for k in 0..reduced.len() {
+            slots.push(if k == position {
+                merge(Arc::new(Template::Slot(i)), Arc::new(Template::Slot(j)))
+            } else {
+                Arc::new(Template::Slot(rest.next().unwrap()))
+            });
+        }
+        let mut cache = HashMap::new();
+        for template in huffman_templates(&reduced, memo).iter() {
+            templates.push(substitute(template, &slots, &mut cache));
+        }
+    }
+    let templates = Arc::new(templates.into_iter().unique().collect_vec());
+    memo.insert(weights.to_vec(), templates.clone());
+    templates
+}
+
+fn instantiate<T>(
+    template: &Arc<Template>,
+    nodes: &[Node<T>],
+    cache: &mut HashMap<*const Template, Node<T>>,
+) -> Node<T>
+where
+    T: RealNum,
+{
+    if let Some(node) = cache.get(&Arc::as_ptr(template)) {
+        return node.clone();
+    }
+    let node = match &**template {
+        Template::Slot(slot) => nodes[*slot].clone(),
+        Template::Merge(a, b) => Node::new_branch(
+            instantiate(a, nodes, cache),
+            instantiate(b, nodes, cache),
+        ),
+    };
+    cache.insert(Arc::as_ptr(template), node.clone());
+    node
+}
+
+//Every Huffman tree for the leaves, once per tree up to swapping children
+pub fn distinct_huffman_trees<T>(mut nodes: Vec<Node<T>>) -> Vec<Node<T>>
+where
+    T: RealNum,
+{
+    if nodes.is_empty() {
+        return vec![];
+    }
+    nodes.sort();
+    let weights = nodes.iter().map(Node::probability).collect_vec();
+    let templates = huffman_templates(&weights, &mut HashMap::new());
+    let mut cache = HashMap::new();
+    templates
+        .iter()
+        .map(|template| instantiate(template, &nodes, &mut cache))
+        .collect_vec()
+}
+
+//A Huffman tree for every distinct Huffman code of the leaves
+pub fn distinct_huffman_codes<T>(nodes: Vec<Node<T>>) -> Vec<Node<T>>
+where
+    T: RealNum,
+{
+    distinct_reductions_by(nodes, |node| {
+        Code::from_node(node)
+            .into_iter()
+            .map(|(code_word, depth)| {
+                (code_word.source_symbol().clone(), depth)
+            })
+            .sorted()
+            .collect_vec()
+    })
+}
+
+//Number of distinct Huffman trees for the weights, up to swapping children.
+//Huffman's algorithm runs in phases: the k nodes of the smallest weight are
+//paired up among themselves, and if k is odd the one left over is merged
+//with one of the c nodes of the next smallest weight. Every choice leaves
+//the same weights for the next phase and can be read back off the finished
+//tree, so the count is the product over phases of the number of perfect
+//matchings, (k - 1)!!, or k (k - 2)!! c for odd k. No weights have no
+//tree.
+pub fn count_huffman_trees<T>(weights: &[T]) -> BigUint
+where
+    T: RealNum,
+{
+    fn double_factorial(n: usize) -> BigUint {
+        (1..=n).rev().step_by(2).map(BigUint::from).product()
+    }
+
+    if weights.is_empty() {
+        return BigUint::zero();
+    }
+
+    let mut counts: BTreeMap<T, usize> =
+        weights.iter().cloned().counts().into_iter().collect();
+    let mut trees = BigUint::one();
+    while counts.values().sum::<usize>() > 1 {
+        let (smallest, k) = counts.pop_first().unwrap();
+        let doubled = smallest.clone() + smallest.clone();
+        if k >= 2 {
+            *counts.entry(doubled).or_default() += k / 2;
+        }
+        if k.is_multiple_of(2) {
+            trees *= double_factorial(k - 1);
+            continue;
+        }
+        trees *= BigUint::from(k) * double_factorial(k.saturating_sub(2));
+        let mut next = counts.first_entry().unwrap();
+        trees *= BigUint::from(*next.get());
+        let merged = smallest + next.key().clone();
+        *next.get_mut() -= 1;
+        if *next.get() == 0 {
+            next.remove();
+        }
+        *counts.entry(merged).or_default() += 1;
+    }
+    trees
+}
+
+//The (weight class, depth) of every leaf below a node, sorted
+type ClassDepths = Vec<(usize, Depth)>;
+
+//A partial reduction up to relabeling symbols of equal weight: how many
+//nodes there are of each weight and ClassDepths
+type ClassForest<T> = BTreeMap<(T, ClassDepths), usize>;
+
+fn join(a: &ClassDepths, b: &ClassDepths) -> ClassDepths {
+    a.iter()
+        .chain(b.iter())
+        .map(|&(class, depth)| (class, depth + 1))
+        .sorted()
+        .collect_vec()
+}
+
+//Every multiset of pairs that pairs up all of `nodes`, a list of node kinds
+//with their multiplicities. The first kind's nodes are split between pairs
+//among themselves and pairs with each later kind, so no multiset is
+//produced twice.
+fn pairings(
+    nodes: &[(ClassDepths, usize)],
+) -> Vec<Vec<(ClassDepths, ClassDepths)>> {
+    fn partners(
+        first: &ClassDepths,
+        unpaired: usize,
+        rest: &mut [(ClassDepths, usize)],
+        start: usize,
+        pairs: &mut Vec<(ClassDepths, ClassDepths)>,
+        out: &mut Vec<Vec<(ClassDepths, ClassDepths)>>,
+    ) {
+        if unpaired == 0 {
+            for mut pairing in pairings(rest) {
+                pairing.extend(pairs.iter().cloned());
+                out.push(pairing);
+            }
+            return;
+        }
+        for i in start..rest.len() {
+            for taken in 1..=rest[i].1.min(unpaired) {
+                rest[i].1 -= taken;
+                let pair = (first.clone(), rest[i].0.clone());
+                pairs.extend(std::iter::repeat_n(pair, taken));
+                partners(first, unpaired - taken, rest, i + 1, pairs, out);
+                pairs.truncate(pairs.len() - taken);
+                rest[i].1 += taken;
+            }
+        }
+    }
+
+    let Some(((first, count), rest)) = nodes.split_first() else {
+        return vec![vec![]];
+    };
+    let mut rest = rest.to_vec();
+    let mut out = vec![];
+    for among_themselves in 0..=count / 2 {
+        let mut pairs = vec![(first.clone(), first.clone()); among_themselves];
+        let unpaired = count - 2 * among_themselves;
+        partners(first, unpaired, &mut rest, 0, &mut pairs, &mut out);
+    }
+    out
+}
+
+fn next_class_forests<T>(forest: &ClassForest<T>) -> Vec<ClassForest<T>>
+where
+    T: RealNum,
+{
+    let smallest = forest.keys().next().unwrap().0.clone();
+    let doubled = smallest.clone() + smallest.clone();
+    let mut rest = forest.clone();
+    let mut lightest = vec![];
+    while let Some(entry) = rest.first_entry() {
+        if entry.key().0 != smallest {
+            break;
+        }
+        let ((_, class_depths), count) = entry.remove_entry();
+        lightest.push((class_depths, count));
+    }
+    let with_pairs = |pairing: Vec<(ClassDepths, ClassDepths)>| {
+        let mut next = rest.clone();
+        for (a, b) in pairing {
+            *next.entry((doubled.clone(), join(&a, &b))).or_default() += 1;
+        }
+        next
+    };
+
+    let k: usize = lightest.iter().map(|(_, count)| count).sum();
+    if k.is_multiple_of(2) {
+        return pairings(&lightest)
+            .into_iter()
+            .map(with_pairs)
+            .collect_vec();
+    }
+    let mut forests = vec![];
+    for i in 0..lightest.len() {
+        let mut paired = lightest.clone();
+        paired[i].1 -= 1;
+        let left_over = &lightest[i].0;
+        for next in pairings(&paired).into_iter().map(with_pairs) {
+            let second = next.keys().next().unwrap().0.clone();
+            let partners = next
+                .keys()
+                .take_while(|(weight, _)| *weight == second)
+                .cloned()
+                .collect_vec();
+            for partner in partners {
+                let mut next = next.clone();
+                let count = next.get_mut(&partner).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    next.remove(&partner);
+                }
+                let joined = join(left_over, &partner.1);
+                let merged = smallest.clone() + second.clone();
+                *next.entry((merged, joined)).or_default() += 1;
+                forests.push(next);
+            }
+        }
+    }
+    forests
+}
+
+//The depths Huffman codes give each weight class, as sorted (class, depth)
+//pairs, once each. Every Huffman code for the weights is one of these with
+//the depths of each class handed out to its symbols in any order.
+fn huffman_class_profiles<T>(weights: &[T]) -> Vec<ClassDepths>
+where
+    T: RealNum,
+{
+    let classes = weights.iter().cloned().sorted().dedup().collect_vec();
+    let mut forest = ClassForest::new();
+    for weight in weights {
+        let class = classes.binary_search(weight).unwrap();
+        *forest
+            .entry((weight.clone(), vec![(class, 0)]))
+            .or_default() += 1;
+    }
+    let mut forests = HashSet::from([forest]);
+    //Every choice leaves the same weights, so all forests finish together
+    while forests
+        .iter()
+        .next()
+        .is_some_and(|forest| forest.values().sum::<usize>() > 1)
+    {
+        forests = forests.iter().flat_map(next_class_forests).collect();
+    }
+    forests
+        .into_iter()
+        .filter_map(|forest| forest.into_keys().next())
+        .map(|(_, class_depths)| class_depths)
+        .collect_vec()
+}
+
+//Number of distinct Huffman codes, as assignments of lengths to symbols,
+//and of distinct Huffman length profiles, as multisets of lengths
+pub fn count_huffman_codes<T>(weights: &[T]) -> (BigUint, usize)
+where
+    T: RealNum,
+{
+    fn factorial(n: usize) -> BigUint {
+        (1..=n).map(BigUint::from).product()
+    }
+
+    let profiles = huffman_class_profiles(weights);
+    let codes = profiles
+        .iter()
+        .map(|profile| {
+            let mut codes = BigUint::one();
+            for (_, class) in &profile.iter().group_by(|(class, _)| *class) {
+                let depths = class.map(|(_, depth)| depth).counts();
+                let size = depths.values().sum();
+                codes *= factorial(size)
+                    / depths
+                        .values()
+                        .map(|&n| factorial(n))
+                        .product::<BigUint>();
+            }
+            codes
+        })
+        .sum();
+    let length_profiles = profiles
+        .iter()
+        .map(|profile| {
+            profile
+                .iter()
+                .map(|(_, depth)| depth)
+                .sorted()
+                .collect_vec()
+        })
+        .unique()
+        .count();
+    (codes, length_profiles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::Source;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn all_possible_reductions_test() {
+        let leaves = vec![
+            Node::new_leaf(1, 'a'),
+            Node::new_leaf(1, 'b'),
+            Node::new_leaf(2, 'c'),
+            Node::new_leaf(2, 'd'),
+        ];
+        let huff_a = Node::new_branch(
+            leaves[3].clone(),
+            Node::new_branch(
+                leaves[2].clone(),
+                Node::new_branch(leaves[0].clone(), leaves[1].clone()),
+            ),
+        );
+        let huff_b = Node::new_branch(
+            leaves[2].clone(),
+            Node::new_branch(
+                leaves[3].clone(),
+                Node::new_branch(leaves[0].clone(), leaves[1].clone()),
+            ),
+        );
+
+        let huff_c = Node::new_branch(
+            Node::new_branch(leaves[3].clone(), leaves[2].clone()),
+            Node::new_branch(leaves[0].clone(), leaves[1].clone()),
+        );
+
+        let reductions = all_possible_reductions(leaves.clone());
+        assert!(!reductions.is_empty() && reductions.len() <= 4 * 3 * 2);
+        assert!(reductions.iter().any(|node| node.is_same_as(&huff_a)));
+        assert!(reductions.iter().any(|node| node.is_same_as(&huff_b)));
+        assert!(reductions.iter().any(|node| node.is_same_as(&huff_c)));
+
+        let distinct = distinct_huffman_trees(leaves.clone());
+        assert_eq!(distinct.len(), 3);
+        for huff in [huff_a, huff_b, huff_c] {
+            assert!(distinct.iter().any(|node| node.is_same_as(&huff)));
+        }
+        assert_eq!(distinct_huffman_codes(leaves).len(), 3);
+    }
+
+    #[test]
+    fn distinct_reductions_test() {
+        let mut rng = StdRng::seed_from_u64(19);
+        for size in 2..=8 {
+            let weights = (0..size).map(|_| rng.gen_range(1..=3));
+            let source = Source::from_weights(weights.collect_vec());
+            let all = all_possible_reductions(source.to_leaves_vec());
+            let trees = distinct_huffman_trees(source.to_leaves_vec());
+            let codes = distinct_huffman_codes(source.to_leaves_vec());
+            assert_eq!(
+                trees.len(),
+                all.iter().map(Node::canonical_form).unique().count()
+            );
+            assert!(trees.iter().map(Node::canonical_form).all_unique());
+
+            let all_codes = all.iter().map(Code::from_node).collect_vec();
+            let codes = codes.iter().map(Code::from_node).collect_vec();
+            assert!(all_codes.iter().all(|code| codes.contains(code)));
+            assert!(codes.iter().all(|code| all_codes.contains(code)));
+            assert!(codes.iter().tuple_combinations().all(|(a, b)| a != b));
+        }
+    }
+
+    #[test]
+    fn distinct_huffman_trees_test() {
+        //Dyadic sources tie at every merge
+        for size in [1, 2, 3, 5, 8] {
+            let leaves = Source::from_weights(vec![1; size]).to_leaves_vec();
+            let expected = distinct_reductions_by(leaves.clone(), |node| {
+                node.canonical_form()
+            });
+            let trees = distinct_huffman_trees(leaves);
+            assert!(trees.iter().map(Node::canonical_form).all_unique());
+            assert_eq!(
+                trees
+                    .iter()
+                    .map(Node::canonical_form)
+                    .sorted()
+                    .collect_vec(),
+                expected
+                    .iter()
+                    .map(Node::canonical_form)
+                    .sorted()
+                    .collect_vec()
+            );
+        }
+        //Eight symbols of equal weight have 8!/2^7 balanced trees
+        let leaves = Source::from_weights(vec![1; 8]).to_leaves_vec();
+        assert_eq!(distinct_huffman_trees(leaves).len(), 315);
+        assert!(distinct_huffman_trees(Vec::<Node<u32>>::new()).is_empty());
+    }
+
+    #[test]
+    fn count_huffman_test() {
+        let mut rng = StdRng::seed_from_u64(23);
+        for size in 1..=9 {
+            for max_weight in [1, 2, 4, 9] {
+                let weights = (0..size)
+                    .map(|_| rng.gen_range(1..=max_weight))
+                    .collect_vec();
+                let source = Source::from_weights(weights.clone());
+                let trees = distinct_huffman_trees(source.to_leaves_vec());
+                assert_eq!(
+                    count_huffman_trees(&weights),
+                    BigUint::from(trees.len())
+                );
+                let codes = distinct_huffman_codes(source.to_leaves_vec())
+                    .iter()
+                    .map(Code::from_node)
+                    .collect_vec();
+                let length_profiles = codes
+                    .iter()
+                    .map(|code| code.values().sorted().collect_vec())
+                    .unique()
+                    .count();
+                assert_eq!(
+                    count_huffman_codes(&weights),
+                    (BigUint::from(codes.len()), length_profiles)
+                );
+            }
+        }
+
+        //A thousand equal weights give every balanced tree, with 24 symbols
+        //at depth 9 and 976 at depth 10
+        let weights = vec![1u32; 1000];
+        let (codes, length_profiles) = count_huffman_codes(&weights);
+        let choose_24: BigUint =
+            (977..=1000u32).map(BigUint::from).product::<BigUint>()
+                / (1..=24u32).map(BigUint::from).product::<BigUint>();
+        assert_eq!(codes, choose_24);
+        assert_eq!(length_profiles, 1);
+        assert!(count_huffman_trees(&weights) > BigUint::from(u128::MAX));
+
+        let empty = Source::<u32>::from_weights(vec![]);
+        assert_eq!(empty.count_huffman_trees(), BigUint::zero());
+        assert_eq!(empty.count_huffman_codes(), (BigUint::zero(), 0));
+    }
+}
@@ -0,0 +1,181 @@
+use crate::node::{Node, NodeType, RealNum};
+use crate::source::Symbol;
+
+use std::fmt::{Display, Write};
+
+//Graphviz DOT for a tree, with weights on branches, symbols and weights on
+//leaves and edges labeled by the bit they stand for. With `highlight` the
+//leaves flagged by is_probably_competitively_optimal are filled in.
+pub fn to_dot<T>(node: &Node<T>, highlight: bool) -> String
+where
+    T: RealNum + Display,
+{
+    fn helper<T: RealNum + Display>(
+        node: &Node<T>,
+        flagged: &[Symbol],
+        next_id: &mut usize,
+        dot: &mut String,
+    ) -> usize {
+        let id = *next_id;
+        *next_id += 1;
+        match node.node_type() {
+            NodeType::Leaf(symbol) => {
+                let fill = if flagged.contains(symbol) {
+                    ", style=filled, fillcolor=salmon"
+                } else {
+                    ""
+                };
+                writeln!(
+                    dot,
+                    "    n{} [label=\"{}\\n{}\", shape=box{}];",
+                    id,
+                    escape(&symbol.to_string()),
+                    node.probability(),
+                    fill
+                )
+                .unwrap();
+            }
+            NodeType::Branch(children) => {
+                writeln!(
+                    dot,
+                    "    n{} [label=\"{}\"];",
+                    id,
+                    node.probability()
+                )
+                .unwrap();
+                for (bit, child) in children.iter().enumerate() {
+                    let child_id = helper(child, flagged, next_id, dot);
+                    writeln!(
+                        dot,
+                        "    n{} -> n{} [label=\"{}\"];",
+                        id, child_id, bit
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        id
+    }
+    let flagged = if highlight {
+        node.flagged_leaves()
+    } else {
+        vec![]
+    };
+    let mut dot = String::from("digraph {\n    node [shape=circle];\n");
+    helper(node, &flagged, &mut 0, &mut dot);
+    dot.push_str("}\n");
+    dot
+}
+
+//A DOT string literal's contents, with quotes, backslashes and line breaks
+//escaped
+fn escape(label: &str) -> String {
+    let mut escaped = String::with_capacity(label.len());
+    for c in label.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+//An indented drawing of a tree for the terminal, one node per line with
+//the first child above the second
+pub fn to_ascii<T>(node: &Node<T>) -> String
+where
+    T: RealNum + Display,
+{
+    fn helper<T: RealNum + Display>(
+        node: &Node<T>,
+        prefix: &str,
+        ascii: &mut String,
+    ) {
+        let NodeType::Branch(children) = node.node_type() else {
+            return;
+        };
+        for (i, child) in children.iter().enumerate() {
+            let (branch, indent) = if i + 1 < children.len() {
+                ("├── ", "│   ")
+            } else {
+                ("└── ", "    ")
+            };
+            writeln!(ascii, "{}{}{}", prefix, branch, label(child)).unwrap();
+            helper(child, &format!("{}{}", prefix, indent), ascii);
+        }
+    }
+    fn label<T: RealNum + Display>(node: &Node<T>) -> String {
+        match node.node_type() {
+            NodeType::Leaf(symbol) => {
+                format!("{} ({})", symbol, node.probability())
+            }
+            NodeType::Branch(_) => node.probability().to_string(),
+        }
+    }
+    let mut ascii = label(node);
+    ascii.push('\n');
+    helper(node, "", &mut ascii);
+    ascii
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree() -> Node<u32> {
+        Node::new_branch(
+            Node::new_leaf(4, 'a'),
+            Node::new_branch(
+                Node::new_leaf(3, 'b'),
+                Node::new_branch(
+                    Node::new_leaf(2, 'c'),
+                    Node::new_leaf(1, 'd'),
+                ),
+            ),
+        )
+    }
+
+    #[test]
+    fn to_ascii_test() {
+        assert_eq!(
+            to_ascii(&tree()),
+            "10\n\
+             ├── a (4)\n\
+             └── 6\n    \
+                 ├── b (3)\n    \
+                 └── 3\n        \
+                     ├── c (2)\n        \
+                     └── d (1)\n"
+        );
+        assert_eq!(to_ascii(&Node::new_leaf(1, 'a')), "a (1)\n");
+    }
+
+    #[test]
+    fn to_dot_test() {
+        let dot = to_dot(&tree(), false);
+        assert!(dot.starts_with("digraph {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains("    n0 [label=\"10\"];\n"));
+        assert!(dot.contains("    n1 [label=\"a\\n4\", shape=box];\n"));
+        assert!(dot.contains("    n0 -> n1 [label=\"0\"];\n"));
+        assert!(dot.contains("    n0 -> n2 [label=\"1\"];\n"));
+        assert_eq!(dot.matches("->").count(), 6);
+        assert!(!dot.contains("filled"));
+
+        //Only d weighs less than a sibling difference above it
+        let dot = to_dot(&tree(), true);
+        assert_eq!(dot.matches("filled").count(), 1);
+        assert!(dot.contains("d\\n1\", shape=box, style=filled"));
+
+        let tree = Node::new_branch(
+            Node::new_leaf(1, "say \"hi\""),
+            Node::new_leaf(1, "a\\b\nc"),
+        );
+        let dot = to_dot(&tree, false);
+        assert!(dot.contains("[label=\"say \\\"hi\\\"\\n1\", shape=box]"));
+        assert!(dot.contains("[label=\"a\\\\b\\nc\\n1\", shape=box]"));
+    }
+}
@@ -0,0 +1,119 @@
+use crate::code::{Advantage, Code, CompetitiveOrd, Probability};
+use crate::conjectures::{Sample, Witness};
+use crate::files::{load_json, save_json};
+use crate::node::Node;
+use crate::source::{Source, SourceSeed};
+
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+//How the witness code fares against another code, from the witness's side:
+//an advantage with more lost than gained means `code` beats the witness
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Comparison {
+    #[serde(with = "crate::code::code_entries")]
+    pub code: Code<Probability>,
+    pub competitive_advantage: Advantage<Probability>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Counterexample {
+    pub conjecture: String,
+    pub source_seed: Option<SourceSeed>,
+    pub source: Source<Probability>,
+    pub huffman_trees: Vec<Node<Probability>>,
+    pub witness_tree: Node<Probability>,
+    #[serde(with = "crate::code::code_entries")]
+    pub witness_code: Code<Probability>,
+    pub comparisons: Vec<Comparison>,
+}
+
+impl Comparison {
+    pub fn new(
+        witness_code: &Code<Probability>,
+        code: &Code<Probability>,
+    ) -> Comparison {
+        Comparison {
+            code: code.clone(),
+            competitive_advantage: witness_code
+                .competitive_advantage(code)
+                .unwrap(),
+        }
+    }
+}
+
+impl Counterexample {
+    pub fn new(
+        conjecture: &str,
+        sample: &Sample,
+        witness: Witness,
+    ) -> Counterexample {
+        Counterexample {
+            conjecture: conjecture.to_string(),
+            source_seed: sample.source_seed,
+            source: sample.source.clone(),
+            huffman_trees: sample.huffman_trees().to_vec(),
+            witness_tree: witness.tree,
+            witness_code: witness.code,
+            comparisons: witness.comparisons,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        save_json(self, path)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Counterexample> {
+        load_json(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::FromNode;
+    use crate::distribution::Distribution;
+
+    #[test]
+    fn save_load_test() {
+        let source = Source::from_vec(vec![('a', 1), ('b', 1), ('c', 2)]);
+        let tree = Node::new_huffman(source.to_leaves_vec()).unwrap();
+        let code = Code::from_node(&tree);
+        let source_seed =
+            SourceSeed::new(3, 4).with_distribution(Distribution::Zipf(1.5));
+        let counterexample = Counterexample {
+            conjecture: "test".to_string(),
+            source_seed: Some(source_seed),
+            source,
+            huffman_trees: vec![tree.clone()],
+            witness_tree: tree.clone(),
+            witness_code: code.clone(),
+            comparisons: vec![Comparison::new(&code, &code)],
+        };
+        let path = std::env::temp_dir().join("save_load_test.json");
+        counterexample.save(&path).unwrap();
+        let loaded = Counterexample::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.conjecture, "test");
+        assert_eq!(loaded.source_seed, Some(source_seed));
+        assert!(loaded.witness_tree.is_same_as(&tree));
+        assert!(loaded.huffman_trees[0].is_same_as(&tree));
+        assert_eq!(loaded.witness_code, code);
+        assert_eq!(loaded.comparisons[0].code, code);
+        assert_eq!(
+            loaded.comparisons[0].competitive_advantage,
+            Advantage::zero()
+        );
+
+        //Reports from before distributions were recorded are uniform
+        let old: SourceSeed =
+            serde_json::from_str(r#"{"seed": 3, "index": 4}"#).unwrap();
+        assert_eq!(old, SourceSeed::new(3, 4));
+        assert!(serde_json::from_str::<SourceSeed>(
+            r#"{"seed": 3, "index": 4, "distribution": "zipf:inf"}"#
+        )
+        .is_err());
+    }
+}
@@ -1,26 +1,196 @@
+use crate::distribution::Distribution;
+use crate::reduction;
 use crate::{node::RealNum, Node};
 
 use itertools::Itertools;
-use rand::{thread_rng, Rng};
-use std::iter::zip;
+use num::integer::Integer;
+use num::{BigInt, BigRational, BigUint, One, Signed, ToPrimitive};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
-#[derive(Clone, Debug)]
-pub struct Source<T>(Vec<(char, T)>);
+//A source symbol, either its position in the source or an arbitrary label.
+//Serialized untagged, so indices are written as numbers and labels as
+//strings.
+#[derive(
+    Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(untagged)]
+pub enum Symbol {
+    Index(usize),
+    Label(Arc<str>),
+}
 
-const ASCII: [char; 52] = [
-    'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o',
-    'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', 'A', 'B', 'C', 'D',
-    'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O', 'P', 'Q', 'R', 'S',
-    'T', 'U', 'V', 'W', 'X', 'Y', 'Z',
-];
+impl From<usize> for Symbol {
+    fn from(index: usize) -> Symbol {
+        Symbol::Index(index)
+    }
+}
+
+impl From<char> for Symbol {
+    fn from(label: char) -> Symbol {
+        Symbol::Label(label.to_string().into())
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(label: &str) -> Symbol {
+        Symbol::Label(label.into())
+    }
+}
+
+//Indices print as #N, so they can't be mistaken for labels
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Symbol::Index(index) => write!(f, "#{}", index),
+            Symbol::Label(label) => write!(f, "{}", label),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Source<T>(Vec<(Symbol, T)>);
 
 //const PROBABILITY_GRANULARITY: u32 = ;
 
+//Identifies a sampled source: the source is drawn from `distribution` with
+//stream `index` of the ChaCha generator seeded with `seed`, so it can be
+//regenerated on its own. Seeds saved before there were distributions are
+//uniform.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SourceSeed {
+    pub seed: u64,
+    pub index: u64,
+    #[serde(default)]
+    pub distribution: Distribution,
+}
+
+impl SourceSeed {
+    pub fn new(seed: u64, index: u64) -> SourceSeed {
+        SourceSeed {
+            seed,
+            index,
+            distribution: Distribution::Uniform,
+        }
+    }
+
+    pub fn with_distribution(self, distribution: Distribution) -> SourceSeed {
+        SourceSeed {
+            distribution,
+            ..self
+        }
+    }
+
+    pub fn rng(&self) -> ChaCha8Rng {
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        rng.set_stream(self.index);
+        rng
+    }
+}
+
+//Hands out consecutive indices under a single seed. Indices are claimed
+//atomically, so a generator can be shared between rayon workers and every
+//source it produces is still replayable from its SourceSeed alone.
+#[derive(Debug)]
+pub struct SourceGenerator {
+    seed: u64,
+    distribution: Distribution,
+    next_index: AtomicU64,
+}
+
+impl SourceGenerator {
+    pub fn new(seed: u64) -> SourceGenerator {
+        SourceGenerator {
+            seed,
+            distribution: Distribution::Uniform,
+            next_index: AtomicU64::new(0),
+        }
+    }
+
+    pub fn with_distribution(
+        self,
+        distribution: Distribution,
+    ) -> SourceGenerator {
+        SourceGenerator {
+            distribution,
+            ..self
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn next_seed(&self) -> SourceSeed {
+        SourceSeed::new(
+            self.seed,
+            self.next_index.fetch_add(1, Ordering::Relaxed),
+        )
+        .with_distribution(self.distribution)
+    }
+
+    pub fn sample(&self, size: usize) -> (SourceSeed, Source<u32>) {
+        let source_seed = self.next_seed();
+        (source_seed, Source::replay(size, source_seed))
+    }
+}
+
+//Every source of `size` positive integer weights summing to at most
+//`max_total`, up to relabeling of the symbols. Weights are yielded in
+//non-decreasing order, and the sources in lexicographic order of weights.
+#[derive(Clone, Debug)]
+pub struct ExhaustiveSources {
+    max_total: u32,
+    weights: Option<Vec<u32>>,
+}
+
+impl ExhaustiveSources {
+    pub fn new(size: usize, max_total: u32) -> ExhaustiveSources {
+        let fits = (size as u64) <= max_total as u64;
+        ExhaustiveSources {
+            max_total,
+            weights: (size > 0 && fits).then(|| vec![1; size]),
+        }
+    }
+
+    fn advance(&mut self) {
+        let Some(weights) = self.weights.as_mut() else {
+            return;
+        };
+        let len = weights.len() as u64;
+        for i in (0..weights.len()).rev() {
+            let prefix_total: u64 =
+                weights[..i].iter().map(|&w| w as u64).sum();
+            let raised = weights[i] as u64 + 1;
+            if prefix_total + raised * (len - i as u64) <= self.max_total as u64
+            {
+                weights[i..].fill(raised as u32);
+                return;
+            }
+        }
+        self.weights = None;
+    }
+}
+
+impl Iterator for ExhaustiveSources {
+    type Item = Source<u32>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let weights = self.weights.clone()?;
+        self.advance();
+        Some(Source::from_weights(weights))
+    }
+}
+
 impl Source<u32> {
     #[allow(dead_code)]
-    fn uniform_int_probabilities(len: usize) -> Vec<u32> {
+    fn uniform_int_probabilities<R: Rng>(len: usize, rng: &mut R) -> Vec<u32> {
         let mut probabilities = vec![0];
-        let mut rng = thread_rng();
         while probabilities.contains(&0) {
             let max_probability: u32 = u32::try_from(len * len).unwrap();
             let mut rand_values: Vec<u32> = (0..len + 1)
@@ -38,27 +208,131 @@ impl Source<u32> {
         probabilities
     }
 
-    fn int_probabilities(len: usize) -> Vec<u32> {
-        let mut rng = thread_rng();
-        let max_probability: u32 = u32::try_from(len * len).unwrap();
+    //Weights below len², or below u32::MAX / len for more than 1625
+    //symbols so that the total fits in a u32. A lone symbol gets weight 1,
+    //as 1..1 is empty.
+    fn int_probabilities<R: Rng>(len: usize, rng: &mut R) -> Vec<u32> {
+        let max_probability =
+            (len * len).min(u32::MAX as usize / len.max(1)).max(2) as u32;
         (0..len)
             .map(|_| rng.gen_range(1..max_probability))
             .collect_vec()
     }
 
-    #[allow(dead_code)]
-    pub fn new_int_uniform(size: usize) -> Source<u32> {
+    pub fn new_int_uniform<R: Rng>(size: usize, rng: &mut R) -> Source<u32> {
+        Source::from_weights(Source::uniform_int_probabilities(size, rng))
+    }
+
+    pub fn new<R: Rng>(size: usize, rng: &mut R) -> Source<u32> {
+        Source::from_weights(Source::int_probabilities(size, rng))
+    }
+    pub fn replay(size: usize, source_seed: SourceSeed) -> Source<u32> {
+        source_seed
+            .distribution
+            .sample(size, &mut source_seed.rng())
+    }
+
+    //How often each symbol occurs, in order of symbol. None if there are
+    //more symbols than a u32 weight can count.
+    pub fn from_frequencies<S: Into<Symbol>>(
+        symbols: impl IntoIterator<Item = S>,
+    ) -> Option<Source<u32>> {
+        let counts = symbols.into_iter().map(Into::into).counts();
+        let total: usize = counts.values().sum();
+        u32::try_from(total).ok()?;
+        Some(Source(
+            counts
+                .into_iter()
+                .map(|(symbol, count)| (symbol, count as u32))
+                .sorted()
+                .collect_vec(),
+        ))
+    }
+
+    //Bytes are indexed by their value
+    pub fn from_bytes(bytes: &[u8]) -> Option<Source<u32>> {
+        Source::from_frequencies(bytes.iter().map(|&byte| byte as usize))
+    }
+
+    pub fn from_chars(text: &str) -> Option<Source<u32>> {
+        Source::from_frequencies(text.chars())
+    }
+
+    //Tokens are separated by whitespace
+    pub fn from_tokens(text: &str) -> Option<Source<u32>> {
+        Source::from_frequencies(text.split_whitespace())
+    }
+
+    pub fn to_rationals(&self) -> Source<BigRational> {
         Source(
-            zip(ASCII, Source::uniform_int_probabilities(size)).collect_vec(),
+            self.0
+                .iter()
+                .map(|(symbol, w)| {
+                    (
+                        symbol.clone(),
+                        BigRational::from_integer(BigInt::from(*w)),
+                    )
+                })
+                .collect_vec(),
         )
     }
+}
 
-    pub fn new(size: usize) -> Source<u32> {
-        Source(zip(ASCII, Source::int_probabilities(size)).collect_vec())
+impl Source<BigRational> {
+    pub fn from_rationals(
+        probabilities: Vec<BigRational>,
+    ) -> Result<Source<BigRational>, String> {
+        if let Some(p) = probabilities.iter().find(|p| !p.is_positive()) {
+            return Err(format!("Probability {} is not positive", p));
+        }
+        Ok(Source::from_weights(probabilities))
     }
-    #[allow(dead_code)]
-    pub fn from_vec(vec: Vec<(char, u32)>) -> Source<u32> {
-        Source(vec)
+
+    //Scales every probability by the least common multiple of the
+    //denominators. Huffman codes and competitive comparisons don't change
+    //under scaling, so the integer source answers the same questions exactly.
+    pub fn to_integer_weights(&self) -> Source<BigUint> {
+        let lcm = self
+            .0
+            .iter()
+            .fold(BigInt::one(), |lcm, (_, p)| lcm.lcm(p.denom()));
+        Source(
+            self.0
+                .iter()
+                .map(|(symbol, p)| {
+                    let weight = p.numer() * (&lcm / p.denom());
+                    (symbol.clone(), weight.to_biguint().unwrap())
+                })
+                .collect_vec(),
+        )
+    }
+
+    pub fn to_u32_weights(&self) -> Option<Source<u32>> {
+        let weights = self.to_integer_weights();
+        let weights = weights
+            .0
+            .iter()
+            .map(|(symbol, w)| Some((symbol.clone(), w.to_u32()?)))
+            .collect::<Option<Vec<_>>>()?;
+        Some(Source(weights))
+    }
+}
+
+//Parses a list of probabilities such as "1/3, 1/3, 1/6, 1/6", separated by
+//commas and/or whitespace
+impl FromStr for Source<BigRational> {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let probabilities = s
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|token| !token.is_empty())
+            .map(|token| {
+                BigRational::from_str(token)
+                    .map_err(|_| format!("Not a rational number: {}", token))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Source::from_rationals(probabilities)
     }
 }
 
@@ -66,15 +340,53 @@ impl<T> Source<T>
 where
     T: RealNum,
 {
+    #[allow(dead_code)]
+    pub fn from_vec<S: Into<Symbol>>(vec: Vec<(S, T)>) -> Source<T> {
+        Source(
+            vec.into_iter()
+                .map(|(symbol, p)| (symbol.into(), p))
+                .collect_vec(),
+        )
+    }
+
+    //Symbols are the indices of the weights
+    pub fn from_weights(weights: Vec<T>) -> Source<T> {
+        Source(
+            weights
+                .into_iter()
+                .enumerate()
+                .map(|(index, p)| (Symbol::Index(index), p))
+                .collect_vec(),
+        )
+    }
+
     #[allow(dead_code)]
     pub fn len(&self) -> usize {
         self.0.len()
     }
 
+    //Counted from the weights alone, without building any trees
+    pub fn count_huffman_trees(&self) -> BigUint {
+        reduction::count_huffman_trees(&self.weights())
+    }
+
+    //Distinct Huffman codes and distinct Huffman length profiles
+    pub fn count_huffman_codes(&self) -> (BigUint, usize) {
+        reduction::count_huffman_codes(&self.weights())
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, (Symbol, T)> {
+        self.0.iter()
+    }
+
+    fn weights(&self) -> Vec<T> {
+        self.0.iter().map(|(_, p)| p.clone()).collect_vec()
+    }
+
     pub fn to_leaves_vec(&self) -> Vec<Node<T>> {
         self.0
             .iter()
-            .map(|(c, p)| Node::new_leaf(*p, *c))
+            .map(|(symbol, p)| Node::new_leaf(p.clone(), symbol.clone()))
             .collect_vec()
     }
 }
@@ -83,11 +395,11 @@ impl<T> Iterator for Source<T>
 where
     T: RealNum,
 {
-    type Item = (char, T);
+    type Item = (Symbol, T);
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some((c, value)) = self.0.pop() {
-            Some((c, value))
+        if let Some((symbol, value)) = self.0.pop() {
+            Some((symbol, value))
         } else {
             None
         }
@@ -97,12 +409,16 @@ where
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::code::{Code, FromNode};
     use crate::node::Node;
+    use rand::thread_rng;
+    use rayon::prelude::*;
+    use std::iter::zip;
 
     #[test]
     fn len_test() {
         let size = 33;
-        let source = Source::new(size);
+        let source = Source::new(size, &mut thread_rng());
         assert!(source.0.len() == size);
     }
 
@@ -110,29 +426,170 @@ mod test {
     fn new_int_uniform_test() {
         let size_usize: usize = 23;
         let size_u32: u32 = 23;
-        let source = Source::new_int_uniform(size_usize);
+        let source = Source::new_int_uniform(size_usize, &mut thread_rng());
         assert!(
-            source.0.iter().map(|(_, i)| i).fold(0, |acc, x| acc + x)
-                == size_u32 * size_u32
+            source.0.iter().map(|(_, i)| i).sum::<u32>() == size_u32 * size_u32
         );
         assert!(!source.0.iter().map(|(_, i)| i).contains(&0));
+        let source = Source::replay(1, SourceSeed::new(1, 0));
+        assert_eq!(source.0, vec![(Symbol::Index(0), 1)]);
     }
 
     #[test]
     fn new_test() {
         let size_usize: usize = 23;
         let size_u32: u32 = 23;
-        let source = Source::new(size_usize);
+        let source = Source::new(size_usize, &mut thread_rng());
         assert!(!source.0.iter().any(|(_, i)| *i > size_u32 * size_u32));
         assert!(!source.0.iter().map(|(_, i)| i).contains(&0));
     }
 
+    #[test]
+    fn replay_test() {
+        let generator = SourceGenerator::new(17);
+        let sampled = (0..100)
+            .into_par_iter()
+            .map(|_| generator.sample(12))
+            .collect::<Vec<_>>();
+        let indices = sampled.iter().map(|(seed, _)| seed.index).sorted();
+        assert!(indices.eq(0..100));
+        for (source_seed, source) in sampled {
+            assert_eq!(source_seed.seed, 17);
+            assert_eq!(Source::replay(12, source_seed).0, source.0);
+        }
+        assert_ne!(
+            Source::replay(12, SourceSeed::new(17, 0)).0,
+            Source::replay(12, SourceSeed::new(18, 0)).0
+        );
+    }
+
+    #[test]
+    fn exhaustive_sources_test() {
+        //Partitions of 3, 4, 5 and 6 into exactly three parts
+        let sources = ExhaustiveSources::new(3, 6)
+            .map(|source| source.0.into_iter().map(|(_, p)| p).collect_vec())
+            .collect_vec();
+        assert_eq!(
+            sources,
+            vec![
+                vec![1, 1, 1],
+                vec![1, 1, 2],
+                vec![1, 1, 3],
+                vec![1, 1, 4],
+                vec![1, 2, 2],
+                vec![1, 2, 3],
+                vec![2, 2, 2],
+            ]
+        );
+        assert_eq!(ExhaustiveSources::new(4, 3).count(), 0);
+        assert_eq!(ExhaustiveSources::new(0, 3).count(), 0);
+        //Partitions of n into at most 5 parts with each part increased by 1
+        let total: usize = (0..=10).map(|n| partitions(n, 5)).sum();
+        assert_eq!(ExhaustiveSources::new(5, 15).count(), total);
+    }
+
+    fn partitions(n: usize, max_parts: usize) -> usize {
+        if n == 0 {
+            return 1;
+        }
+        if max_parts == 0 {
+            return 0;
+        }
+        //Largest part is at most max_parts by conjugation
+        (1..=max_parts.min(n)).map(|k| partitions(n - k, k)).sum()
+    }
+
+    #[test]
+    fn rational_source_test() {
+        let source: Source<BigRational> =
+            "1/3, 1/3 1/6,1/12, 1/12".parse().unwrap();
+        assert_eq!(source.len(), 5);
+        assert_eq!(source.0[2].1, BigRational::new(1.into(), 6.into()));
+        let weights = source.to_u32_weights().unwrap();
+        assert_eq!(
+            weights.0.iter().map(|(_, w)| *w).collect_vec(),
+            vec![4, 4, 2, 1, 1]
+        );
+        assert!("1/3, 0".parse::<Source<BigRational>>().is_err());
+        assert!("1/3, -1/3".parse::<Source<BigRational>>().is_err());
+        assert!("1/3, x".parse::<Source<BigRational>>().is_err());
+
+        let huge: Source<BigRational> = "1/4294967296, 1/2, 1".parse().unwrap();
+        assert!(huge.to_u32_weights().is_none());
+        assert_eq!(huge.to_integer_weights().0[0].1, BigUint::from(1u32));
+    }
+
+    #[test]
+    fn from_frequencies_test() {
+        let source = Source::from_chars("abracadabra").unwrap();
+        assert_eq!(
+            source.0,
+            vec![
+                (Symbol::from('a'), 5),
+                (Symbol::from('b'), 2),
+                (Symbol::from('c'), 1),
+                (Symbol::from('d'), 1),
+                (Symbol::from('r'), 2),
+            ]
+        );
+        let source = Source::from_bytes(&[7, 0, 7, 255]).unwrap();
+        assert_eq!(
+            source.0,
+            vec![
+                (Symbol::Index(0), 1),
+                (Symbol::Index(7), 2),
+                (Symbol::Index(255), 1),
+            ]
+        );
+        let source = Source::from_tokens("the cat  saw\nthe dog").unwrap();
+        assert_eq!(source.len(), 4);
+        assert!(source.0.contains(&(Symbol::from("the"), 2)));
+        assert_eq!(Source::from_chars("").unwrap().len(), 0);
+
+        //Exact probabilities of the counts give back the counts
+        let source = Source::from_chars("abracadabra").unwrap();
+        assert_eq!(source.to_rationals().to_u32_weights().unwrap().0, source.0);
+    }
+
+    #[test]
+    fn large_source_test() {
+        let source = Source::new(1000, &mut thread_rng());
+        let symbols = source.0.iter().map(|(symbol, _)| symbol).collect_vec();
+        assert_eq!(symbols.iter().unique().count(), 1000);
+        assert_eq!(*symbols[999], Symbol::Index(999));
+        let huffman = Node::new_huffman(source.to_leaves_vec()).unwrap();
+        assert_eq!(Code::from_node(&huffman).len(), 1000);
+        assert_eq!(ExhaustiveSources::new(60, 61).count(), 2);
+
+        //Thousands of weights below 3000² would overflow a u32 total
+        let source = Source::replay(3000, SourceSeed::new(9, 0));
+        let total: u64 = source.0.iter().map(|&(_, w)| w as u64).sum();
+        assert!(total <= u32::MAX as u64);
+        let huffman = Node::new_huffman(source.to_leaves_vec()).unwrap();
+        assert_eq!(huffman.probability() as u64, total);
+
+        assert_eq!(Symbol::Index(1).to_string(), "#1");
+        assert_eq!(Symbol::Index(52).to_string(), "#52");
+        assert_ne!(Symbol::Index(0).to_string(), Symbol::from('a').to_string());
+        assert_eq!(Symbol::from("left").to_string(), "left");
+        let json = serde_json::to_string(&source.0[..1]).unwrap();
+        assert!(json.starts_with("[[0,"));
+        let labeled = Source::from_vec(vec![("x", 1), ("y", 2)]);
+        let json = serde_json::to_string(&labeled).unwrap();
+        assert_eq!(json, r#"[["x",1],["y",2]]"#);
+    }
+
     #[test]
     fn to_leaves_vec_test() {
-        let source =
-            Source(vec![('a', 1), ('b', 2), ('c', 3), ('d', 4), ('e', 5)]);
+        let source = Source::from_vec(vec![
+            ('a', 1),
+            ('b', 2),
+            ('c', 3),
+            ('d', 4),
+            ('e', 5),
+        ]);
         let mut leaves_vec = source.to_leaves_vec();
-        let mut leaves_vec_test = vec![
+        let mut leaves_vec_test = [
             Node::new_leaf(1, 'a'),
             Node::new_leaf(2, 'b'),
             Node::new_leaf(3, 'c'),
@@ -141,12 +598,7 @@ mod test {
         ];
         leaves_vec.sort();
         leaves_vec_test.sort();
-        assert!(
-            zip(leaves_vec.iter(), leaves_vec_test.iter())
-                .filter(|(x, y)| (*x) != (*y))
-                .collect_vec()
-                .len()
-                == 0
-        );
+        assert!(zip(leaves_vec.iter(), leaves_vec_test.iter())
+            .all(|(x, y)| (*x) == (*y)));
     }
 }